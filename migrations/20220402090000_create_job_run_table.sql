@@ -0,0 +1,9 @@
+-- Add migration script here
+
+CREATE TABLE IF NOT EXISTS job_run (
+    name TEXT NOT NULL PRIMARY KEY,
+    last_run_at DATETIME NOT NULL,
+    last_duration_ms INTEGER NOT NULL,
+    last_error TEXT,
+    last_success_at DATETIME
+);
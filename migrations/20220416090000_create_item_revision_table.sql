@@ -0,0 +1,9 @@
+-- Earlier versions of items whose content changed
+
+CREATE TABLE IF NOT EXISTS item_revision (
+    item_id INTEGER NOT NULL,
+    revision INTEGER NOT NULL,
+    original TEXT NOT NULL,
+    created_at DATETIME NOT NULL,
+    PRIMARY KEY (item_id, revision)
+);
@@ -0,0 +1,12 @@
+-- Items whose stored record couldn't be read back, moved aside so they can be
+-- fetched again and looked at later
+
+CREATE TABLE IF NOT EXISTS quarantined_item (
+    item_id INTEGER PRIMARY KEY NOT NULL,
+    original BLOB,
+    codec TEXT,
+    reason TEXT NOT NULL,
+    created_at DATETIME NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS quarantined_item_created_at ON quarantined_item (created_at);
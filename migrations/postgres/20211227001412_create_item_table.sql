@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS item (
+    id BIGINT PRIMARY KEY,
+    original TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS item_metric (
+    item_id BIGINT NOT NULL,
+    metric TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    value BIGINT NOT NULL,
+    PRIMARY KEY (item_id, metric, created_at)
+);
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS bookmarked_item (
+    item_id BIGINT NOT NULL,
+    user_id TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (item_id, user_id)
+);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS list (
+    key TEXT NOT NULL,
+    item_id BIGINT NOT NULL,
+    ordering BIGINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (key, item_id)
+);
@@ -0,0 +1,13 @@
+ALTER TABLE item ADD descendants BIGINT; -- story
+ALTER TABLE item ADD username TEXT; -- comment or story
+ALTER TABLE item ADD score BIGINT; -- story, job
+ALTER TABLE item ADD title TEXT; -- story, job
+ALTER TABLE item ADD url TEXT; -- story, job
+ALTER TABLE item ADD body TEXT; -- comment or story, job
+ALTER TABLE item ADD time TIMESTAMPTZ; -- comment or story
+
+CREATE TABLE IF NOT EXISTS item_parent (
+    item_id BIGINT NOT NULL,
+    parent_id BIGINT NOT NULL,
+    ordering BIGINT NOT NULL
+);
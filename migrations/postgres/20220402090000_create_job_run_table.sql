@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS job_run (
+    name TEXT NOT NULL PRIMARY KEY,
+    last_run_at TIMESTAMPTZ NOT NULL,
+    last_duration_ms BIGINT NOT NULL,
+    last_error TEXT,
+    last_success_at TIMESTAMPTZ
+);
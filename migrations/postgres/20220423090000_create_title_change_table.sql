@@ -0,0 +1,13 @@
+-- Edits to story titles and URLs, e.g. by moderators
+
+CREATE TABLE IF NOT EXISTS title_change (
+    item_id BIGINT NOT NULL,
+    old_title TEXT NOT NULL,
+    new_title TEXT NOT NULL,
+    old_url TEXT,
+    new_url TEXT,
+    created_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS title_change_item_id ON title_change (item_id);
+CREATE INDEX IF NOT EXISTS title_change_created_at ON title_change (created_at);
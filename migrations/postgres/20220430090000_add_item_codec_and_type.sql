@@ -0,0 +1,8 @@
+-- How `original` is stored, and the item type, which can't be read out of
+-- compressed JSON
+
+ALTER TABLE item ADD codec TEXT; -- NULL for plain JSON, or zstd
+ALTER TABLE item ADD item_type TEXT; -- story, comment or job
+
+UPDATE item SET item_type = original::json->>'type';
+ALTER TABLE item ALTER COLUMN original TYPE BYTEA USING convert_to(original, 'UTF8');
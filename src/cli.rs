@@ -0,0 +1,323 @@
+//! The command line. Serving is the default; the other commands are
+//! administrative tasks that run against the same database and exit.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand};
+use serde_json::json;
+use sqlx::sqlite::SqlitePool;
+use tracing::{info, warn};
+
+use crate::config::{Config, ConfigArgs};
+use crate::db::{self, Codec, Storage};
+use crate::export::{Filter, Format, Table};
+use crate::{domain, export, import, store::Store};
+
+/// How many items `backfill`, `reindex` and `compress` handle at a time.
+const BATCH_SIZE: u32 = 1_000;
+
+#[derive(Debug, Parser)]
+#[clap(about = "Backend API for twhn")]
+pub struct Cli {
+    #[clap(flatten)]
+    pub config: ConfigArgs,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the API server and background jobs (the default)
+    Serve,
+    /// Migrate the database, then exit
+    Migrate,
+    /// Fetch an item from HN into the database
+    Fetch {
+        id: u32,
+        /// Also fetch everything below it in the thread
+        #[clap(long)]
+        tree: bool,
+    },
+    /// Fetch a range of items from HN into the database
+    Backfill {
+        #[clap(long)]
+        from: u32,
+        /// [default: the newest item]
+        #[clap(long)]
+        to: Option<u32>,
+    },
+    /// Rebuild the item table's columns from the stored original JSON
+    Reindex,
+    /// Rewrite the stored original JSON of every item with a codec, e.g. to
+    /// compress rows written before `item_codec` was set
+    Compress {
+        /// `zstd` or `plain`
+        #[clap(long, default_value = "zstd")]
+        codec: Codec,
+    },
+    /// Print what's in the database
+    Stats,
+    /// List stored items that couldn't be read back and were set aside, as
+    /// JSON lines, newest first
+    Quarantined {
+        #[clap(long, default_value = "50")]
+        limit: u32,
+    },
+    /// Compact the database file
+    Vacuum,
+    /// Write a table out as JSON lines, CSV or Parquet
+    Export {
+        /// [default: stdout]
+        #[clap(long, short)]
+        output: Option<PathBuf>,
+        #[clap(long, arg_enum, default_value = "items")]
+        table: Table,
+        #[clap(long, arg_enum, default_value = "jsonl")]
+        format: Format,
+        #[clap(flatten)]
+        filter: Filter,
+    },
+    /// Load items from JSON lines, such as an HN dump, which may be gzipped.
+    /// An interrupted import of a file carries on where it stopped.
+    Import {
+        /// [default: stdin]
+        input: Option<PathBuf>,
+        /// How many items to write per transaction
+        #[clap(long, default_value = "10000")]
+        batch_size: usize,
+        /// Write lines that aren't valid items here, as JSON lines
+        #[clap(long)]
+        rejects: Option<PathBuf>,
+        /// Start from the top of the file, even if an earlier import got further
+        #[clap(long)]
+        restart: bool,
+    },
+}
+
+/// Run an administrative command. The database is already migrated.
+pub async fn run(command: Command, config: &Config, storage: &Arc<dyn Storage>) -> Result<()> {
+    let store = Store::new(storage.clone(), config);
+
+    match command {
+        Command::Serve => bail!("serving isn't an administrative command"),
+        Command::Migrate => {
+            info!("Migrated to schema version {}", db::migration::latest());
+        }
+        Command::Fetch { id, tree } => fetch(&store, id, tree).await?,
+        Command::Backfill { from, to } => backfill(&store, from, to).await?,
+        Command::Reindex => reindex(&**storage).await?,
+        Command::Compress { codec } => compress(&**storage, codec).await?,
+        Command::Stats => {
+            let stats = storage.stats().await?;
+            println!("{}", serde_json::to_string_pretty(&stats)?);
+        }
+        Command::Quarantined { limit } => {
+            for item in storage.quarantined_items(limit).await? {
+                let item = json!({
+                    "item_id": item.item_id,
+                    "codec": item.codec,
+                    "original": item.json(),
+                    "reason": item.reason,
+                    "created_at": item.created_at,
+                });
+                println!("{}", item);
+            }
+        }
+        Command::Vacuum => vacuum(sqlite(storage, "vacuum")?).await?,
+        Command::Export {
+            output,
+            table,
+            format,
+            filter,
+        } => {
+            let pool = sqlite(storage, "export")?;
+            let written = match output {
+                Some(path) => {
+                    let file = File::create(&path)
+                        .with_context(|| format!("creating {}", path.display()))?;
+                    export::export(pool, table, format, &filter, BufWriter::new(file)).await?
+                }
+                None => {
+                    let out = BufWriter::new(io::stdout());
+                    export::export(pool, table, format, &filter, out).await?
+                }
+            };
+            info!("Exported {} rows", written);
+        }
+        Command::Import {
+            input,
+            batch_size,
+            rejects,
+            restart,
+        } => import(&**storage, input, batch_size, rejects, restart).await?,
+    }
+
+    Ok(())
+}
+
+async fn import(
+    storage: &dyn Storage,
+    input: Option<PathBuf>,
+    batch_size: usize,
+    rejects: Option<PathBuf>,
+    restart: bool,
+) -> Result<()> {
+    if batch_size == 0 {
+        bail!("--batch-size must be at least 1");
+    }
+
+    let checkpoint = input.as_deref().map(import::checkpoint_for);
+    if let (Some(key), true) = (&checkpoint, restart) {
+        import::clear_checkpoint(storage, key).await?;
+    }
+
+    let mut rejects = match rejects {
+        Some(path) => {
+            let file =
+                File::create(&path).with_context(|| format!("creating {}", path.display()))?;
+            Some(BufWriter::new(file))
+        }
+        None => None,
+    };
+    let options = import::Options {
+        batch_size,
+        checkpoint,
+        rejects: rejects.as_mut().map(|rejects| rejects as &mut dyn Write),
+    };
+
+    let summary = import::import(storage, import::open(input.as_deref())?, options).await?;
+    info!(
+        "Imported {} items, rejected {}, skipped {} already imported lines",
+        summary.imported, summary.rejected, summary.resumed_after
+    );
+    Ok(())
+}
+
+async fn fetch(store: &Store, id: u32, tree: bool) -> Result<()> {
+    if store.get_and_store_item(id).await?.is_none() {
+        bail!("HN has no item {}", id);
+    }
+
+    if tree {
+        let descendants = store.get_descendants(id).await?;
+        info!("Fetched item {} and {} below it", id, descendants.len());
+    } else {
+        info!("Fetched item {}", id);
+    }
+
+    Ok(())
+}
+
+async fn backfill(store: &Store, from: u32, to: Option<u32>) -> Result<()> {
+    let to = match to {
+        Some(to) => to,
+        None => store.get_max_item_id().await?,
+    };
+    if from > to {
+        bail!("--from {} is after --to {}", from, to);
+    }
+
+    let mut start = from;
+    loop {
+        let end = start.saturating_add(BATCH_SIZE - 1).min(to);
+        let stored = store
+            .get_and_store_items((start..=end).collect())
+            .await?
+            .len();
+        info!("Backfilled {}..={} ({} items)", start, end, stored);
+
+        if end == to {
+            break;
+        }
+        start = end + 1;
+    }
+
+    Ok(())
+}
+
+/// Re-derive every row from `original`, e.g. after a column is added.
+async fn reindex(storage: &dyn Storage) -> Result<()> {
+    let mut after = 0;
+    let mut reindexed = 0;
+    let mut skipped = 0;
+
+    loop {
+        let page = storage.items_after(after, BATCH_SIZE).await?;
+        let last = match page.last() {
+            Some(last) => last.id(),
+            None => break,
+        };
+
+        let mut rebuilt = vec![];
+        for item in &page {
+            let parsed = domain::Item::try_from(item.clone()).and_then(db::Item::try_from);
+            match parsed {
+                Ok(parsed) => rebuilt.push(parsed),
+                Err(err) => {
+                    warn!(id = item.id(), error = %err, "Can't parse stored item");
+                    skipped += 1;
+                }
+            }
+        }
+        storage.insert_items(&rebuilt, None).await?;
+        reindexed += rebuilt.len();
+
+        after = last;
+    }
+
+    info!("Reindexed {} items, skipped {}", reindexed, skipped);
+    Ok(())
+}
+
+/// Re-encode every row's `original` with `codec`.
+async fn compress(storage: &dyn Storage, codec: Codec) -> Result<()> {
+    let mut after = 0;
+    let mut seen = 0;
+    let mut recoded = 0;
+
+    loop {
+        let page = storage.items_after(after, BATCH_SIZE).await?;
+        let last = match page.last() {
+            Some(last) => last.id(),
+            None => break,
+        };
+
+        recoded += storage.recode_items(&page, codec).await?;
+        seen += page.len();
+        after = last;
+    }
+
+    info!("Rewrote {} of {} items as {}", recoded, seen, codec);
+    Ok(())
+}
+
+/// The SQLite pool behind `storage`, for commands that only support SQLite.
+fn sqlite<'a>(storage: &'a Arc<dyn Storage>, command: &str) -> Result<&'a SqlitePool> {
+    match storage.sqlite() {
+        Some(pool) => Ok(pool),
+        None => bail!("{} only supports SQLite so far", command),
+    }
+}
+
+async fn vacuum(pool: &SqlitePool) -> Result<()> {
+    let before = database_size(pool).await?;
+    sqlx::query("VACUUM").execute(pool).await?;
+    let after = database_size(pool).await?;
+
+    info!("Vacuumed the database from {} to {} bytes", before, after);
+    Ok(())
+}
+
+async fn database_size(pool: &SqlitePool) -> Result<i64> {
+    let (size,) = sqlx::query_as(
+        "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(size)
+}
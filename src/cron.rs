@@ -1,235 +1,501 @@
-use crate::result::Result;
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::db::{Storage, TitleChange};
+use crate::domain::Item;
+use crate::events::{Event, Events};
+use crate::metrics::{time_query, CURSORS};
+use crate::result::{Error, Result};
+use crate::scheduler::Scheduler;
+use crate::shutdown::Shutdown;
 use crate::store::Store;
-use chrono::{DateTime, Utc};
-use sqlx::sqlite::SqlitePool;
-use tokio::time::{sleep, Duration};
-
-pub async fn start(store: Store, pool: SqlitePool) {
-    println!("Starting background work...");
-    let pool_ = pool.clone();
-    let store_ = store.clone();
-
-    loop {
-        if let Ok(top_stories) = store.get_top_stories().await {
-            println!("Got top stories, saving rank...");
-
-            let now = Utc::now();
-            let result = save_rank(&pool, top_stories.clone(), now).await;
-            if result.is_err() {
-                println!("Got an error from save rank: {:?}", result);
-            }
+use chrono::Utc;
+use futures::StreamExt;
+use tracing::{debug, error, info};
+
+/// Build the schedule for all background work.
+pub fn scheduler(
+    store: Store,
+    storage: Arc<dyn Storage>,
+    events: Events,
+    config: &Config,
+) -> Scheduler {
+    let schedule = config.effective_jobs();
+    let jobs = Jobs {
+        store,
+        storage: storage.clone(),
+        events,
+    };
+
+    Scheduler::new(storage)
+        .job("rank", schedule.rank, {
+            let jobs = jobs.clone();
+            move || jobs.clone().rank()
+        })
+        .job("updates", schedule.updates, {
+            let jobs = jobs.clone();
+            move || jobs.clone().updates()
+        })
+        .job("firehose", schedule.firehose, {
+            let jobs = jobs.clone();
+            move || jobs.clone().firehose()
+        })
+        .job("backfill", schedule.backfill, {
+            let jobs = jobs.clone();
+            move || jobs.clone().backfill()
+        })
+        .job("cleanup", schedule.cleanup, move || jobs.clone().cleanup())
+}
 
-            // Cache the items
-            let result = store.get_items(top_stories).await;
-            if result.is_err() {
-                println!("Got an error from loading item and children: {:?}", result);
-            }
-        }
-        sleep(Duration::from_secs(20)).await;
+#[derive(Clone)]
+struct Jobs {
+    store: Store,
+    storage: Arc<dyn Storage>,
+    events: Events,
+}
 
-        if let Ok(updates) = store.get_updates().await {
-            println!("Got updates");
+impl Jobs {
+    async fn rank(self) -> Result<()> {
+        let top_stories = self.store.get_top_stories().await?;
+        snapshot_top_stories(&*self.storage, &self.store, &self.events, top_stories).await
+    }
 
-            let result = store.get_and_store_items(updates.items).await;
-            if result.is_err() {
-                println!("Got an error from loading updates: {:?}", result);
+    async fn updates(self) -> Result<()> {
+        let updates = self.store.get_updates().await?;
+        store_updates(&*self.storage, &self.store, &self.events, updates.items).await
+    }
+
+    async fn firehose(self) -> Result<()> {
+        let max_item = self.store.get_max_item_id().await?;
+        firehose(&*self.storage, &self.store, &self.events, max_item).await
+    }
+
+    async fn backfill(self) -> Result<()> {
+        backfill_some(&*self.storage, &self.store, BACKFILL_BATCH).await
+    }
+
+    async fn cleanup(self) -> Result<()> {
+        let cutoff = Utc::now() - chrono::Duration::days(METRIC_RETENTION_DAYS);
+        self.storage.delete_metrics_before(cutoff).await
+    }
+}
+
+/// Like the polling jobs, but reacts to the streaming API instead of a timer.
+/// Stops taking new events on shutdown.
+pub async fn start_streaming(
+    store: Store,
+    storage: Arc<dyn Storage>,
+    events: Events,
+    shutdown: Shutdown,
+) {
+    info!("Starting background work from the streaming API...");
+
+    let top_stories = async {
+        let mut stream = Box::pin(store.watch_top_stories().take_until(shutdown.wait()));
+        while let Some(top_stories) = stream.next().await {
+            let result = snapshot_top_stories(&*storage, &store, &events, top_stories).await;
+            if let Err(err) = result {
+                error!(error = ?err, "Couldn't save top stories");
+            }
+        }
+    };
+
+    let updates = async {
+        let mut stream = Box::pin(store.watch_updates().take_until(shutdown.wait()));
+        while let Some(updates) = stream.next().await {
+            let result = store_updates(&*storage, &store, &events, updates.items).await;
+            if let Err(err) = result {
+                error!(error = ?err, "Couldn't load updates");
+            }
+        }
+    };
+
+    let max_item = async {
+        let mut stream = Box::pin(store.watch_max_item_id().take_until(shutdown.wait()));
+        while let Some(max_item) = stream.next().await {
+            let result = firehose(&*storage, &store, &events, max_item).await;
+            if let Err(err) = result {
+                error!(error = ?err, "Couldn't run the firehose");
             }
         }
-        sleep(Duration::from_secs(20)).await;
+    };
+
+    tokio::join!(top_stories, updates, max_item);
+}
+
+async fn snapshot_top_stories(
+    storage: &dyn Storage,
+    store: &Store,
+    events: &Events,
+    top_stories: Vec<u32>,
+) -> Result<()> {
+    debug!(count = top_stories.len(), "Got top stories, saving rank");
+
+    let changed = storage.save_rank(&top_stories, Utc::now());
+    if time_query("save_rank", changed).await? {
+        events.publish(Event::ListChanged {
+            key: "top_stories".into(),
+            ids: top_stories.iter().copied().take(30).collect(),
+        });
     }
+
+    // Cache the items
+    store.get_items(top_stories).await?;
+
+    Ok(())
 }
 
-async fn save_rank(pool: &SqlitePool, top_stories: Vec<u32>, ts: DateTime<Utc>) -> Result<()> {
-    #[derive(Debug)]
-    struct ExistingMetric {
-        value: i64,
+/// How many new items the firehose fetches at once.
+const FIREHOSE_BATCH: u32 = 100;
+
+/// Store every item created since the last run, up to `max_item`. The cursor
+/// only moves once a batch is stored, so a failure is retried next time
+/// instead of leaving a gap.
+async fn firehose(
+    storage: &dyn Storage,
+    store: &Store,
+    events: &Events,
+    max_item: u32,
+) -> Result<()> {
+    let mut cursor = match load_cursor(storage, "firehose_ptr").await? {
+        Some(cursor) => cursor,
+        None => {
+            // Start from now; older items are the backfill's job
+            save_cursor(storage, "firehose_ptr", max_item).await?;
+            return Ok(());
+        }
+    };
+
+    while cursor < max_item {
+        let end = (cursor + FIREHOSE_BATCH).min(max_item);
+        debug!(from = cursor + 1, to = end, "Firehose fetching");
+
+        store_updates(storage, store, events, (cursor + 1..=end).collect()).await?;
+        save_cursor(storage, "firehose_ptr", end).await?;
+        cursor = end;
     }
 
-    let mut tx = pool.begin().await?;
+    Ok(())
+}
 
-    // Delete the old top items
-    sqlx::query!("DELETE FROM list WHERE key = 'top_stories'")
-        .execute(&mut tx)
-        .await?;
+async fn load_cursor(storage: &dyn Storage, key: &str) -> Result<Option<u32>> {
+    storage
+        .get_config(key)
+        .await?
+        .map(|value| {
+            value
+                .parse::<u32>()
+                .map_err(|err| Error::corrupt("config", key, err))
+        })
+        .transpose()
+}
 
-    // Save the rank
-    for (ordering, id) in top_stories.into_iter().take(30).enumerate() {
-        let id = id as i64;
-        let ordering = ordering as i64;
-        let rank = (ordering + 1) as i64;
-
-        // Save the current list
-        sqlx::query!(
-            r#"
-        INSERT INTO list (key, item_id, ordering, created_at)
-        VALUES ('top_stories', ?1, ?2, ?3)
-        "#,
-            id,
-            ordering,
-            ts,
-        )
-        .execute(&mut tx)
-        .await?;
+async fn save_cursor(storage: &dyn Storage, key: &str, value: u32) -> Result<()> {
+    storage.set_config(key, &value.to_string()).await?;
+    CURSORS.with_label_values(&[key]).set(value.into());
 
-        // Get latest value
-        let existing = sqlx::query_as!(
-            ExistingMetric,
-            r#"
-                SELECT value FROM item_metric
-                WHERE metric = 'rank'
-                AND item_id = ?1
-                ORDER BY created_at DESC
-                LIMIT 1
-                "#,
-            id,
-        )
-        .fetch_optional(&mut tx)
-        .await?;
+    Ok(())
+}
 
-        let should_save = match existing {
-            Some(ex) if ex.value != rank => true,
-            None => true,
-            _ => false,
-        };
-
-        if should_save {
-            sqlx::query!(
-                r#"
-                    INSERT INTO item_metric (item_id, metric, created_at, value)
-                    VALUES (?1, 'rank', ?2, ?3)
-                    "#,
+/// Store the items, record stories whose title or URL changed and tell
+/// subscribers about them.
+async fn store_updates(
+    storage: &dyn Storage,
+    store: &Store,
+    events: &Events,
+    ids: Vec<u32>,
+) -> Result<()> {
+    let stored = store.get_stored_items(&ids).await?;
+
+    let items = store.get_and_store_items(ids).await?;
+
+    for (id, item) in items {
+        if let Some(change) = stored.get(&id).and_then(|old| title_change(old, &item)) {
+            info!(
                 id,
-                ts,
-                rank
-            )
-            .execute(&mut tx)
-            .await?;
+                old = %change.old_title,
+                new = %change.new_title,
+                "Story title changed"
+            );
+            storage.record_title_change(&change).await?;
         }
-    }
 
-    tx.commit().await?;
+        if matches!(item, Item::Comment(_)) && !stored.contains_key(&id) {
+            let story_id = store
+                .get_ancestors(id)
+                .await?
+                .into_values()
+                .find_map(|ancestor| match ancestor {
+                    Item::Story(story) => Some(story.id),
+                    _ => None,
+                });
+
+            if let Some(story_id) = story_id {
+                events.publish(Event::NewComment {
+                    story_id,
+                    comment: item.clone(),
+                });
+            }
+        }
+
+        events.publish(Event::ItemUpdated(item));
+    }
 
     Ok(())
 }
 
-async fn backfill_some(pool: &SqlitePool, store: &Store, limit: u32) -> Result<()> {
-    let max_item = store.get_max_item_id().await?;
-
-    let start = sqlx::query!("SELECT value FROM config WHERE key='backfill_ptr'")
-        .fetch_optional(&*pool)
-        .await?
-        .map(|v| v.value.parse::<u32>().unwrap())
-        .unwrap_or(0);
-    let end = (start + limit).min(max_item);
+/// How a story's title or URL changed between two versions of it, if it did.
+/// Deleting a story clears its title, which doesn't count.
+fn title_change(old: &Item, new: &Item) -> Option<TitleChange> {
+    match (old, new) {
+        (Item::Story(old), Item::Story(new))
+            if !new.deleted && (old.title != new.title || old.url != new.url) =>
+        {
+            Some(TitleChange {
+                item_id: new.id as i64,
+                old_title: old.title.clone(),
+                new_title: new.title.clone(),
+                old_url: old.url.clone(),
+                new_url: new.url.clone(),
+                created_at: Utc::now().naive_utc(),
+            })
+        }
+        _ => None,
+    }
+}
 
-    let range = (start..=end)
-        .into_iter()
-        .map(|v| v as u32)
-        .collect::<Vec<_>>();
+/// How many old items the backfill fetches per run.
+const BACKFILL_BATCH: u32 = 1_000;
 
-    let _ = store.get_and_store_items(range).await;
+/// Store the next `limit` items after the backfill cursor. Like the
+/// firehose, the cursor only moves once they're stored.
+async fn backfill_some(storage: &dyn Storage, store: &Store, limit: u32) -> Result<()> {
+    let max_item = store.get_max_item_id().await?;
 
-    sqlx::query!(
-        "INSERT OR REPLACE INTO config (key, value) VALUES ('backfill_ptr', ?1)",
-        end
-    )
-    .execute(&*pool)
-    .await?;
+    let start = load_cursor(storage, "backfill_ptr").await?.unwrap_or(0);
+    let end = (start + limit).min(max_item);
+    if start >= end {
+        return Ok(());
+    }
 
-    Ok(())
+    store
+        .get_and_store_items((start + 1..=end).collect())
+        .await?;
+    save_cursor(storage, "backfill_ptr", end).await
 }
 
+/// How long rank history is kept when cleanup is enabled.
+const METRIC_RETENTION_DAYS: i64 = 90;
+
 #[cfg(test)]
 mod test {
-    use super::save_rank;
-    use chrono::{Duration, Utc};
+    use super::{backfill_some, firehose, load_cursor, save_cursor, store_updates};
+    use crate::db::{SqliteStorage, Storage};
+    use crate::{events::Events, hn_client::HnClient, store::Store};
+    use serde_json::json;
     use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
     use std::str::FromStr;
+    use std::sync::Arc;
+    use warp::Filter;
 
-    async fn setup() -> SqlitePool {
+    async fn setup() -> (SqlitePool, Arc<dyn Storage>) {
         let options = SqliteConnectOptions::from_str("sqlite::memory:").unwrap();
         let pool = SqlitePoolOptions::new().connect_lazy_with(options);
         sqlx::migrate!().run(&pool).await.unwrap();
-        pool
+        (pool.clone(), Arc::new(SqliteStorage::new(pool)))
+    }
+
+    /// HN's API with items up to 1000, where item 666 always fails.
+    fn stand_in_store(storage: &Arc<dyn Storage>) -> Store {
+        let max_item = warp::path!("v0" / "maxitem.json").map(|| warp::reply::json(&1000));
+        let items = warp::path!("v0" / "item" / String).map(|file: String| {
+            let id = file.trim_end_matches(".json").parse::<u32>().unwrap();
+            if id == 666 {
+                return warp::reply::with_status(
+                    warp::reply::json(&json!({ "error": "oops" })),
+                    warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                );
+            }
+            let item = match id {
+                1 => json!({
+                    "type": "story",
+                    "id": 1,
+                    "by": "dan",
+                    "descendants": 0,
+                    "score": 1,
+                    "title": "Hello",
+                    "time": 1640995200,
+                }),
+                _ => json!({
+                    "type": "comment",
+                    "id": id,
+                    "by": "dan",
+                    "parent": 1,
+                    "text": "hi",
+                    "time": 1640995200,
+                }),
+            };
+            warp::reply::with_status(warp::reply::json(&item), warp::http::StatusCode::OK)
+        });
+        let (addr, server) = warp::serve(max_item.or(items)).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let client = HnClient::with_base_url(
+            &format!("http://{}/v0", addr),
+            std::time::Duration::from_secs(10),
+        );
+        Store::with_client(storage.clone(), client)
     }
 
     #[tokio::test]
-    async fn saves_ranks_when_none_exist() {
-        let pool = setup().await;
+    async fn firehose_starts_at_the_current_max_item() {
+        let (pool, storage) = setup().await;
+        let store = stand_in_store(&storage);
 
-        save_rank(&pool, vec![40], Utc::now()).await.unwrap();
+        firehose(&*storage, &store, &Events::new(16), 500)
+            .await
+            .unwrap();
 
-        let got: Vec<(i64, i64)> = sqlx::query_as("SELECT item_id, value FROM item_metric")
+        let got: Vec<(i64,)> = sqlx::query_as("SELECT id FROM item")
             .fetch_all(&pool)
             .await
             .unwrap();
-
-        let want = vec![(40, 1)];
-        assert_eq!(got, want)
+        assert_eq!(got, vec![]);
+        assert_eq!(
+            load_cursor(&*storage, "firehose_ptr").await.unwrap(),
+            Some(500)
+        );
     }
 
     #[tokio::test]
-    async fn saves_ranks_when_duplicate_exists() {
-        let pool = setup().await;
-        let t1 = Utc::now() - Duration::seconds(1);
-        let t2 = Utc::now();
+    async fn firehose_stores_every_new_item() {
+        let (pool, storage) = setup().await;
+        let store = stand_in_store(&storage);
+        save_cursor(&*storage, "firehose_ptr", 10).await.unwrap();
 
-        save_rank(&pool, vec![40], t1).await.unwrap();
-        save_rank(&pool, vec![40], t2).await.unwrap();
+        firehose(&*storage, &store, &Events::new(16), 250)
+            .await
+            .unwrap();
 
-        let got: Vec<(i64, i64)> = sqlx::query_as("SELECT item_id, value FROM item_metric")
+        let got: Vec<(i64,)> = sqlx::query_as("SELECT id FROM item WHERE id > 1 ORDER BY id")
             .fetch_all(&pool)
             .await
             .unwrap();
-
-        let want = vec![(40, 1)];
-        assert_eq!(got, want)
+        let want = (11..=250).map(|id| (id,)).collect::<Vec<_>>();
+        assert_eq!(got, want);
+        assert_eq!(
+            load_cursor(&*storage, "firehose_ptr").await.unwrap(),
+            Some(250)
+        );
     }
 
     #[tokio::test]
-    async fn saves_ranks_when_duplicate_exists_but_rank_changes() {
-        let pool = setup().await;
-        let t4 = Utc::now();
-        let t3 = t4 - Duration::seconds(1);
-        let t2 = t3 - Duration::seconds(1);
-        let t1 = t2 - Duration::seconds(1);
-
-        save_rank(&pool, vec![40, 41], t1).await.unwrap();
-        save_rank(&pool, vec![40, 41], t2).await.unwrap();
-        save_rank(&pool, vec![41, 40], t3).await.unwrap();
-        save_rank(&pool, vec![40, 41], t4).await.unwrap();
-
-        let got: Vec<(i64,)> = sqlx::query_as("SELECT  value FROM item_metric WHERE item_id = 40")
+    async fn firehose_keeps_its_cursor_when_upstream_fails() {
+        let (pool, storage) = setup().await;
+        let store = stand_in_store(&storage);
+        save_cursor(&*storage, "firehose_ptr", 600).await.unwrap();
+
+        assert!(firehose(&*storage, &store, &Events::new(16), 700)
+            .await
+            .is_err());
+
+        let got: Vec<(i64,)> = sqlx::query_as("SELECT id FROM item")
             .fetch_all(&pool)
             .await
             .unwrap();
-
-        let want = vec![(1,), (2,), (1,)];
-        assert_eq!(got, want)
+        assert_eq!(got, vec![]);
+        assert_eq!(
+            load_cursor(&*storage, "firehose_ptr").await.unwrap(),
+            Some(600)
+        );
     }
 
     #[tokio::test]
-    async fn saves_top_stories() {
-        let pool = setup().await;
+    async fn backfill_keeps_its_cursor_when_upstream_fails() {
+        let (_, storage) = setup().await;
+        let store = stand_in_store(&storage);
+
+        backfill_some(&*storage, &store, 500).await.unwrap();
+        assert_eq!(
+            load_cursor(&*storage, "backfill_ptr").await.unwrap(),
+            Some(500)
+        );
+
+        assert!(backfill_some(&*storage, &store, 500).await.is_err());
+        assert_eq!(
+            load_cursor(&*storage, "backfill_ptr").await.unwrap(),
+            Some(500)
+        );
+    }
 
-        save_rank(&pool, vec![40], Utc::now()).await.unwrap();
+    #[tokio::test]
+    async fn store_updates_records_title_changes() {
+        let (_, storage) = setup().await;
+        let store = stand_in_store(&storage);
+        let stored = json!({
+            "type": "story",
+            "id": 1,
+            "by": "dan",
+            "descendants": 0,
+            "score": 1,
+            "title": "Hi",
+            "url": "https://dan.com",
+            "time": 1640995200,
+        });
+        let stored = serde_json::from_value::<crate::domain::Item>(stored).unwrap();
+        storage
+            .insert_items(&[stored.try_into().unwrap()], None)
+            .await
+            .unwrap();
 
-        let got: Vec<(i64, i64)> = sqlx::query_as("SELECT item_id, ordering FROM list")
-            .fetch_all(&pool)
+        store_updates(&*storage, &store, &Events::new(16), vec![1, 2])
+            .await
+            .unwrap();
+        store_updates(&*storage, &store, &Events::new(16), vec![1, 2])
             .await
             .unwrap();
 
-        let want = vec![(40, 0)];
+        let got = storage
+            .title_changes(1)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|change| {
+                (
+                    change.old_title,
+                    change.new_title,
+                    change.old_url,
+                    change.new_url,
+                )
+            })
+            .collect::<Vec<_>>();
+        let want = vec![(
+            "Hi".to_string(),
+            "Hello".to_string(),
+            Some("https://dan.com".to_string()),
+            None,
+        )];
         assert_eq!(got, want);
+        assert!(storage.title_changes(2).await.unwrap().is_empty());
+    }
 
-        save_rank(&pool, vec![41], Utc::now()).await.unwrap();
+    #[tokio::test]
+    async fn store_updates_quarantines_unreadable_items() {
+        let (pool, storage) = setup().await;
+        let store = stand_in_store(&storage);
+        sqlx::query("INSERT INTO item (id, original) VALUES (2, '{\"type\":\"comm')")
+            .execute(&pool)
+            .await
+            .unwrap();
 
-        let got: Vec<(i64, i64)> = sqlx::query_as("SELECT item_id, ordering FROM list")
-            .fetch_all(&pool)
+        store_updates(&*storage, &store, &Events::new(16), vec![2])
             .await
             .unwrap();
 
-        let want = vec![(41, 0)];
-        assert_eq!(got, want);
+        let quarantined = storage.quarantined_items(10).await.unwrap();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(quarantined[0].item_id, 2);
+        assert!(quarantined[0].reason.starts_with("corrupt item record 2"));
+        assert!(store.get_item(2).await.unwrap().is_some());
     }
 }
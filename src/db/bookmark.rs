@@ -0,0 +1,194 @@
+//! Items saved for later, kept in the `bookmarked_item` table.
+
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
+use sqlx::sqlite::SqlitePool;
+
+use crate::result::Result;
+
+#[derive(Clone)]
+pub struct Bookmarks {
+    pool: SqlitePool,
+}
+
+impl Bookmarks {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    /// Bookmarked item ids, most recently bookmarked first.
+    pub async fn ids(&self) -> Result<Vec<u32>> {
+        let ids = sqlx::query!(
+            r#"
+            SELECT
+                item_id
+            FROM
+                bookmarked_item
+            ORDER BY
+                created_at DESC;
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| row.item_id as u32)
+        .collect();
+
+        Ok(ids)
+    }
+
+    /// Item ids `user_id` bookmarked, most recently bookmarked first.
+    pub async fn ids_for(&self, user_id: &str) -> Result<Vec<u32>> {
+        let ids = sqlx::query!(
+            r#"
+            SELECT
+                item_id
+            FROM
+                bookmarked_item
+            WHERE
+                user_id = ?1
+            ORDER BY
+                created_at DESC;
+            "#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| row.item_id as u32)
+        .collect();
+
+        Ok(ids)
+    }
+
+    /// Which of `ids` are bookmarked.
+    pub async fn among(&self, ids: &[u32]) -> Result<HashSet<u32>> {
+        // SQLite has no array binding, so pass the ids as a JSON array
+        let ids = serde_json::to_string(ids).unwrap_or_default();
+        let bookmarked = sqlx::query!(
+            r#"
+            SELECT
+                item_id
+            FROM
+                bookmarked_item
+            WHERE
+                item_id IN (SELECT value FROM json_each(?1))
+            "#,
+            ids
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| row.item_id as u32)
+        .collect();
+
+        Ok(bookmarked)
+    }
+
+    pub async fn add(&self, item_id: u32, user_id: &str, at: DateTime<Utc>) -> Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO
+                bookmarked_item (item_id, user_id, created_at)
+            VALUES
+                (?1, ?2, ?3)
+            "#,
+            item_id,
+            user_id,
+            at
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove(&self, item_id: u32, user_id: &str) -> Result<()> {
+        sqlx::query!(
+            r#"
+            DELETE FROM
+                bookmarked_item
+            WHERE
+                item_id = ?1
+            AND
+                user_id = ?2
+            "#,
+            item_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Duration;
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+    use std::str::FromStr;
+
+    async fn setup() -> Bookmarks {
+        let options = SqliteConnectOptions::from_str("sqlite::memory:").unwrap();
+        let pool = SqlitePoolOptions::new().connect_lazy_with(options);
+        sqlx::migrate!().run(&pool).await.unwrap();
+        Bookmarks::new(pool)
+    }
+
+    #[tokio::test]
+    async fn lists_newest_first() {
+        let bookmarks = setup().await;
+        let now = Utc::now();
+
+        bookmarks.add(1, "dan", now).await.unwrap();
+        bookmarks
+            .add(2, "dan", now + Duration::seconds(1))
+            .await
+            .unwrap();
+
+        assert_eq!(bookmarks.ids().await.unwrap(), vec![2, 1]);
+    }
+
+    #[tokio::test]
+    async fn lists_by_user() {
+        let bookmarks = setup().await;
+        let now = Utc::now();
+
+        bookmarks.add(1, "dan", now).await.unwrap();
+        bookmarks.add(2, "bob", now).await.unwrap();
+        bookmarks
+            .add(3, "dan", now + Duration::seconds(1))
+            .await
+            .unwrap();
+
+        assert_eq!(bookmarks.ids_for("dan").await.unwrap(), vec![3, 1]);
+        assert!(bookmarks.ids_for("eve").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn removes_bookmarks() {
+        let bookmarks = setup().await;
+        bookmarks.add(1, "dan", Utc::now()).await.unwrap();
+        bookmarks.add(2, "dan", Utc::now()).await.unwrap();
+
+        bookmarks.remove(1, "dan").await.unwrap();
+        bookmarks.remove(2, "someone else").await.unwrap();
+
+        assert_eq!(bookmarks.ids().await.unwrap(), vec![2]);
+    }
+
+    #[tokio::test]
+    async fn finds_bookmarked_among_ids() {
+        let bookmarks = setup().await;
+        bookmarks.add(1, "dan", Utc::now()).await.unwrap();
+        bookmarks.add(3, "dan", Utc::now()).await.unwrap();
+
+        let got = bookmarks.among(&[1, 2, 3]).await.unwrap();
+
+        assert_eq!(got, HashSet::from([1, 3]));
+        assert!(bookmarks.among(&[]).await.unwrap().is_empty());
+    }
+}
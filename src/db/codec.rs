@@ -0,0 +1,102 @@
+//! How the JSON in `item.original` is stored. Each row names its codec in
+//! `item.codec`, so rows written before compression, or with it turned off,
+//! still read back.
+
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// zstd's default level: most of the gain for little CPU.
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    /// As text. Rows from before codecs existed have no marker and are plain.
+    Plain,
+    /// zstd-compressed.
+    Zstd,
+}
+
+impl Codec {
+    /// The marker stored in `item.codec`.
+    pub fn marker(self) -> Option<&'static str> {
+        match self {
+            Codec::Plain => None,
+            Codec::Zstd => Some("zstd"),
+        }
+    }
+
+    pub fn from_marker(marker: Option<&str>) -> io::Result<Self> {
+        match marker {
+            None => Ok(Codec::Plain),
+            Some("zstd") => Ok(Codec::Zstd),
+            Some(other) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown codec `{}`", other),
+            )),
+        }
+    }
+
+    pub fn encode(self, json: &str) -> io::Result<Vec<u8>> {
+        match self {
+            Codec::Plain => Ok(json.as_bytes().to_vec()),
+            Codec::Zstd => zstd::encode_all(json.as_bytes(), ZSTD_LEVEL),
+        }
+    }
+
+    pub fn decode(self, original: &[u8]) -> io::Result<String> {
+        let bytes = match self {
+            Codec::Plain => original.to_vec(),
+            Codec::Zstd => zstd::decode_all(original)?,
+        };
+
+        String::from_utf8(bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Codec::Plain => "plain",
+            Codec::Zstd => "zstd",
+        })
+    }
+}
+
+impl FromStr for Codec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "plain" => Ok(Codec::Plain),
+            "zstd" => Ok(Codec::Zstd),
+            _ => anyhow::bail!("expected `plain` or `zstd`, got `{}`", s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let json = r#"{"type":"story","id":1,"title":"Hello"}"#;
+
+        for codec in [Codec::Plain, Codec::Zstd] {
+            let encoded = codec.encode(json).unwrap();
+            let codec = Codec::from_marker(codec.marker()).unwrap();
+            assert_eq!(codec.decode(&encoded).unwrap(), json);
+        }
+        assert_eq!(Codec::Plain.encode(json).unwrap(), json.as_bytes());
+    }
+
+    #[test]
+    fn rejects_unknown_markers_and_bad_data() {
+        assert!(Codec::from_marker(Some("lz4")).is_err());
+        assert!(Codec::Zstd.decode(b"not zstd").is_err());
+    }
+}
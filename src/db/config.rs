@@ -0,0 +1,82 @@
+//! The `config` table: a small key-value store for state that outlives a
+//! process, like ingestion cursors and import checkpoints.
+
+use sqlx::sqlite::{Sqlite, SqlitePool};
+use sqlx::Executor;
+
+use crate::result::Result;
+
+#[derive(Clone)]
+pub struct Settings {
+    pool: SqlitePool,
+}
+
+impl Settings {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<String>> {
+        let value = sqlx::query!("SELECT value FROM config WHERE key = ?1", key)
+            .fetch_optional(&self.pool)
+            .await?
+            .map(|row| row.value);
+
+        Ok(value)
+    }
+
+    pub async fn set(&self, key: &str, value: &str) -> Result<()> {
+        Self::upsert(&self.pool, key, value).await
+    }
+
+    /// Set `key` to `value` through any executor, e.g. as part of a larger
+    /// transaction.
+    pub async fn upsert<'e, E>(executor: E, key: &str, value: &str) -> Result<()>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        sqlx::query!(
+            "INSERT OR REPLACE INTO config (key, value) VALUES (?1, ?2)",
+            key,
+            value
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sqlx::sqlite::SqliteConnectOptions;
+    use sqlx::sqlite::SqlitePoolOptions;
+    use std::str::FromStr;
+
+    async fn setup() -> Settings {
+        let options = SqliteConnectOptions::from_str("sqlite::memory:").unwrap();
+        let pool = SqlitePoolOptions::new().connect_lazy_with(options);
+        sqlx::migrate!().run(&pool).await.unwrap();
+        Settings::new(pool)
+    }
+
+    #[tokio::test]
+    async fn sets_and_replaces_values() {
+        let settings = setup().await;
+        assert_eq!(settings.get("firehose_ptr").await.unwrap(), None);
+
+        settings.set("firehose_ptr", "10").await.unwrap();
+        settings.set("firehose_ptr", "20").await.unwrap();
+        settings.set("backfill_ptr", "5").await.unwrap();
+
+        assert_eq!(
+            settings.get("firehose_ptr").await.unwrap(),
+            Some("20".into())
+        );
+        assert_eq!(
+            settings.get("backfill_ptr").await.unwrap(),
+            Some("5".into())
+        );
+    }
+}
@@ -1,22 +1,28 @@
+use crate::db::Codec;
 use crate::domain;
+use crate::result::{Error, Result};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::{
     query::{Query, QueryAs},
     sqlite::{Sqlite, SqliteArguments},
 };
+use std::io;
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, sqlx::FromRow)]
 pub struct Item {
-    id: i64,
-    original: String,
-    descendants: Option<i64>,
-    username: Option<String>,
-    score: Option<i64>,
-    title: Option<String>,
-    url: Option<String>,
-    body: Option<String>,
-    time: Option<DateTime<Utc>>,
+    pub(super) id: i64,
+    /// The item as HN sent it, in JSON, encoded with `codec`.
+    pub(super) original: Vec<u8>,
+    pub(super) codec: Option<String>,
+    pub(super) item_type: Option<String>,
+    pub(super) descendants: Option<i64>,
+    pub(super) username: Option<String>,
+    pub(super) score: Option<i64>,
+    pub(super) title: Option<String>,
+    pub(super) url: Option<String>,
+    pub(super) body: Option<String>,
+    pub(super) time: Option<DateTime<Utc>>,
 }
 
 impl Item {
@@ -31,15 +37,91 @@ impl Item {
         .bind(id as i64)
     }
 
-    pub fn insert<'a>(&'a self) -> Query<'a, Sqlite, SqliteArguments> {
+    pub fn load_many<'a>(ids: &[u32]) -> QueryAs<'a, Sqlite, Item, SqliteArguments<'a>> {
+        // SQLite has no array binding, so pass the ids as a JSON array
+        sqlx::query_as::<Sqlite, Item>(
+            r#"
+            SELECT * FROM item
+            WHERE id IN (SELECT value FROM json_each(?1))
+            "#,
+        )
+        .bind(serde_json::to_string(ids).unwrap_or_default())
+    }
+
+    /// Up to `limit` items with ids above `after`, in id order, for walking
+    /// the whole table.
+    pub fn page<'a>(after: i64, limit: u32) -> QueryAs<'a, Sqlite, Item, SqliteArguments<'a>> {
+        sqlx::query_as::<Sqlite, Item>(
+            r#"
+            SELECT * FROM item
+            WHERE id > ?1
+            ORDER BY id
+            LIMIT ?2
+            "#,
+        )
+        .bind(after)
+        .bind(limit)
+    }
+
+    /// Up to `limit` stories and jobs whose title contains `query`, ignoring
+    /// case, newest first, after skipping `offset`.
+    pub fn search<'a>(
+        query: &str,
+        limit: u32,
+        offset: u32,
+    ) -> QueryAs<'a, Sqlite, Item, SqliteArguments<'a>> {
+        sqlx::query_as::<Sqlite, Item>(
+            r#"
+            SELECT * FROM item
+            WHERE title LIKE ?1 ESCAPE '\'
+            ORDER BY time DESC, id DESC
+            LIMIT ?2 OFFSET ?3
+            "#,
+        )
+        .bind(contains_pattern(query))
+        .bind(limit)
+        .bind(offset)
+    }
+
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn codec(&self) -> io::Result<Codec> {
+        Codec::from_marker(self.codec.as_deref())
+    }
+
+    /// The item as HN sent it, in JSON.
+    pub fn json(&self) -> io::Result<String> {
+        self.codec()?.decode(&self.original)
+    }
+
+    /// The same item with `original` encoded with `codec`.
+    pub fn encoded(&self, codec: Codec) -> io::Result<Item> {
+        if self.codec()? == codec {
+            return Ok(self.clone());
+        }
+
+        Ok(Item {
+            original: codec.encode(&self.json()?)?,
+            codec: codec.marker().map(String::from),
+            ..self.clone()
+        })
+    }
+
+    /// Plain JSON is stored as text, so it can still be queried with SQLite's
+    /// JSON functions.
+    pub fn insert(&self) -> Query<'_, Sqlite, SqliteArguments<'_>> {
         sqlx::query!(
             r#"
-            INSERT OR REPLACE INTO item (id, original, descendants, username, score, title, url, body, time)
-            VALUES 
-            (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+            INSERT OR REPLACE INTO item (id, original, codec, item_type, descendants, username, score, title, url, body, time)
+            VALUES
+            (?1, CASE WHEN ?3 IS NULL THEN CAST(?2 AS TEXT) ELSE ?2 END, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
             "#,
             self.id,
             self.original,
+            self.codec,
+            self.item_type,
             self.descendants,
             self.username,
             self.score,
@@ -51,13 +133,34 @@ impl Item {
     }
 }
 
-impl From<domain::Item> for Item {
-    fn from(input: domain::Item) -> Self {
-        let original = serde_json::to_string(&input).unwrap();
-        match input {
+/// A `LIKE` pattern matching text that contains `query`, with `\` as the
+/// escape character.
+pub fn contains_pattern(query: &str) -> String {
+    let mut pattern = String::from("%");
+    for c in query.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+impl TryFrom<domain::Item> for Item {
+    type Error = Error;
+
+    fn try_from(input: domain::Item) -> Result<Self> {
+        let original = serde_json::to_string(&input)
+            .map_err(|err| Error::corrupt("item", input.id(), err))?
+            .into_bytes();
+        let codec = None;
+        Ok(match input {
             domain::Item::Story(inner) => Self {
                 id: inner.id as i64,
                 original,
+                codec,
+                item_type: Some("story".into()),
                 descendants: Some(inner.descendants as i64),
                 username: Some(inner.by),
                 score: Some(inner.score as i64),
@@ -69,6 +172,8 @@ impl From<domain::Item> for Item {
             domain::Item::Comment(inner) => Self {
                 id: inner.id as i64,
                 original,
+                codec,
+                item_type: Some("comment".into()),
                 username: Some(inner.by),
                 descendants: None,
                 score: None,
@@ -80,6 +185,8 @@ impl From<domain::Item> for Item {
             domain::Item::Job(inner) => Self {
                 id: inner.id as i64,
                 original,
+                codec,
+                item_type: Some("job".into()),
                 username: None,
                 descendants: None,
                 score: Some(inner.score as i64),
@@ -88,13 +195,18 @@ impl From<domain::Item> for Item {
                 body: inner.text,
                 time: Some(inner.time),
             },
-        }
+        })
     }
 }
 
-impl From<Item> for domain::Item {
-    fn from(input: Item) -> Self {
-        serde_json::from_str(&input.original).unwrap()
+impl TryFrom<Item> for domain::Item {
+    type Error = Error;
+
+    fn try_from(input: Item) -> Result<Self> {
+        let json = input
+            .json()
+            .map_err(|err| Error::corrupt("item", input.id, err))?;
+        serde_json::from_str(&json).map_err(|err| Error::corrupt("item", input.id, err))
     }
 }
 
@@ -115,17 +227,20 @@ mod test {
     #[tokio::test]
     async fn inserts_item() {
         let pool = setup().await;
-        let time = Utc::now().to_string();
+        let time = Utc::now();
 
         let item = Item {
             id: 1,
-            original: "hey".into(),
+            original: b"hey".to_vec(),
+            codec: None,
+            item_type: Some("story".into()),
             descendants: Some(2),
             username: Some("dan".into()),
             score: Some(3),
             title: Some("Title".into()),
             url: Some("https://dan.com".into()),
             body: Some("body".into()),
+            time: Some(time),
         };
 
         item.insert().execute(&pool).await.unwrap();
@@ -134,4 +249,96 @@ mod test {
         let want = item;
         assert_eq!(got, want);
     }
+
+    #[tokio::test]
+    async fn loads_many_items() {
+        let pool = setup().await;
+
+        for id in [1, 2, 3] {
+            let item = Item {
+                id,
+                original: b"hey".to_vec(),
+                codec: None,
+                item_type: Some("story".into()),
+                descendants: None,
+                username: None,
+                score: None,
+                title: None,
+                url: None,
+                body: None,
+                time: None,
+            };
+            item.insert().execute(&pool).await.unwrap();
+        }
+
+        let mut got = Item::load_many(&[1, 3, 4])
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|item| item.id)
+            .collect::<Vec<_>>();
+        got.sort_unstable();
+
+        let want = vec![1, 3];
+        assert_eq!(got, want);
+    }
+
+    #[tokio::test]
+    async fn reads_back_compressed_items() {
+        let pool = setup().await;
+        let json = r#"{"type":"comment","id":1,"by":"dan","kids":null,"parent":2,"text":"hi","time":1175714200}"#;
+        let parsed = serde_json::from_str::<domain::Item>(json).unwrap();
+
+        let item = Item::try_from(parsed)
+            .unwrap()
+            .encoded(Codec::Zstd)
+            .unwrap();
+        item.insert().execute(&pool).await.unwrap();
+
+        let got = Item::load(1).fetch_one(&pool).await.unwrap();
+        assert_eq!(got.codec.as_deref(), Some("zstd"));
+        assert_eq!(got.item_type.as_deref(), Some("comment"));
+        assert_eq!(
+            serde_json::to_string(&domain::Item::try_from(got.clone()).unwrap()).unwrap(),
+            json
+        );
+        assert_eq!(got.encoded(Codec::Plain).unwrap().original, json.as_bytes());
+    }
+
+    #[test]
+    fn reports_corrupt_records() {
+        let item = |original: &[u8], codec: Option<&str>| Item {
+            id: 7,
+            original: original.to_vec(),
+            codec: codec.map(String::from),
+            item_type: None,
+            descendants: None,
+            username: None,
+            score: None,
+            title: None,
+            url: None,
+            body: None,
+            time: None,
+        };
+
+        for bad in [
+            item(b"{not json", None),
+            item(b"{}", Some("lz4")),
+            item(b"not zstd", Some("zstd")),
+        ] {
+            match domain::Item::try_from(bad) {
+                Err(Error::CorruptRecord { table, id, .. }) => {
+                    assert_eq!((table, id.as_str()), ("item", "7"))
+                }
+                other => panic!("expected a corrupt record, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn escapes_like_wildcards() {
+        assert_eq!(contains_pattern("rust"), "%rust%");
+        assert_eq!(contains_pattern("100%_\\"), "%100\\%\\_\\\\%");
+    }
 }
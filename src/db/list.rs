@@ -0,0 +1,222 @@
+//! Ordered lists of items, like the front page, kept in the `list` table.
+
+use chrono::{DateTime, Utc};
+use sqlx::sqlite::SqlitePool;
+
+use crate::result::Result;
+
+#[derive(Clone)]
+pub struct Lists {
+    pool: SqlitePool,
+}
+
+impl Lists {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    /// The item ids on a list, in order.
+    pub async fn load(&self, key: &str) -> Result<Vec<u32>> {
+        let ids = sqlx::query!(
+            r#"
+            SELECT
+                item_id
+            FROM
+                list
+            WHERE
+                key = ?1
+            ORDER BY
+               ordering ASC
+            "#,
+            key
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| row.item_id as u32)
+        .collect();
+
+        Ok(ids)
+    }
+
+    /// Replace the top stories list and record the rank of every story whose
+    /// rank changed. Returns whether any did.
+    pub async fn save_rank(&self, top_stories: &[u32], ts: DateTime<Utc>) -> Result<bool> {
+        #[derive(Debug)]
+        struct ExistingMetric {
+            value: i64,
+        }
+
+        let mut tx = self.pool.begin().await?;
+        let mut changed = false;
+
+        // Delete the old top items
+        sqlx::query!("DELETE FROM list WHERE key = 'top_stories'")
+            .execute(&mut tx)
+            .await?;
+
+        // Save the rank
+        for (ordering, id) in top_stories.iter().take(30).enumerate() {
+            let id = *id as i64;
+            let ordering = ordering as i64;
+            let rank = ordering + 1;
+
+            // Save the current list
+            sqlx::query!(
+                r#"
+            INSERT INTO list (key, item_id, ordering, created_at)
+            VALUES ('top_stories', ?1, ?2, ?3)
+            "#,
+                id,
+                ordering,
+                ts,
+            )
+            .execute(&mut tx)
+            .await?;
+
+            // Get latest value
+            let existing = sqlx::query_as!(
+                ExistingMetric,
+                r#"
+                SELECT value FROM item_metric
+                WHERE metric = 'rank'
+                AND item_id = ?1
+                ORDER BY created_at DESC
+                LIMIT 1
+                "#,
+                id,
+            )
+            .fetch_optional(&mut tx)
+            .await?;
+
+            let should_save = match existing {
+                Some(ex) if ex.value != rank => true,
+                None => true,
+                _ => false,
+            };
+
+            if should_save {
+                changed = true;
+                sqlx::query!(
+                    r#"
+                    INSERT INTO item_metric (item_id, metric, created_at, value)
+                    VALUES (?1, 'rank', ?2, ?3)
+                    "#,
+                    id,
+                    ts,
+                    rank
+                )
+                .execute(&mut tx)
+                .await?;
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(changed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Duration;
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+    use std::str::FromStr;
+
+    async fn setup() -> (SqlitePool, Lists) {
+        let options = SqliteConnectOptions::from_str("sqlite::memory:").unwrap();
+        let pool = SqlitePoolOptions::new().connect_lazy_with(options);
+        sqlx::migrate!().run(&pool).await.unwrap();
+        (pool.clone(), Lists::new(pool))
+    }
+
+    #[tokio::test]
+    async fn saves_ranks_when_none_exist() {
+        let (pool, lists) = setup().await;
+
+        lists.save_rank(&[40], Utc::now()).await.unwrap();
+
+        let got: Vec<(i64, i64)> = sqlx::query_as("SELECT item_id, value FROM item_metric")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+
+        let want = vec![(40, 1)];
+        assert_eq!(got, want)
+    }
+
+    #[tokio::test]
+    async fn saves_ranks_when_duplicate_exists() {
+        let (pool, lists) = setup().await;
+        let t1 = Utc::now() - Duration::seconds(1);
+        let t2 = Utc::now();
+
+        lists.save_rank(&[40], t1).await.unwrap();
+        lists.save_rank(&[40], t2).await.unwrap();
+
+        let got: Vec<(i64, i64)> = sqlx::query_as("SELECT item_id, value FROM item_metric")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+
+        let want = vec![(40, 1)];
+        assert_eq!(got, want)
+    }
+
+    #[tokio::test]
+    async fn reports_whether_ranks_changed() {
+        let (_, lists) = setup().await;
+        let t3 = Utc::now();
+        let t2 = t3 - Duration::seconds(1);
+        let t1 = t2 - Duration::seconds(1);
+
+        assert!(lists.save_rank(&[40, 41], t1).await.unwrap());
+        assert!(!lists.save_rank(&[40, 41], t2).await.unwrap());
+        assert!(lists.save_rank(&[41, 40], t3).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn saves_ranks_when_duplicate_exists_but_rank_changes() {
+        let (pool, lists) = setup().await;
+        let t4 = Utc::now();
+        let t3 = t4 - Duration::seconds(1);
+        let t2 = t3 - Duration::seconds(1);
+        let t1 = t2 - Duration::seconds(1);
+
+        lists.save_rank(&[40, 41], t1).await.unwrap();
+        lists.save_rank(&[40, 41], t2).await.unwrap();
+        lists.save_rank(&[41, 40], t3).await.unwrap();
+        lists.save_rank(&[40, 41], t4).await.unwrap();
+
+        let got: Vec<(i64,)> = sqlx::query_as("SELECT  value FROM item_metric WHERE item_id = 40")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+
+        let want = vec![(1,), (2,), (1,)];
+        assert_eq!(got, want)
+    }
+
+    #[tokio::test]
+    async fn saves_top_stories() {
+        let (_, lists) = setup().await;
+
+        lists.save_rank(&[40], Utc::now()).await.unwrap();
+        assert_eq!(lists.load("top_stories").await.unwrap(), vec![40]);
+
+        lists.save_rank(&[41, 40], Utc::now()).await.unwrap();
+        assert_eq!(lists.load("top_stories").await.unwrap(), vec![41, 40]);
+    }
+
+    #[tokio::test]
+    async fn keeps_the_top_thirty() {
+        let (_, lists) = setup().await;
+        let ids = (1..=40).collect::<Vec<_>>();
+
+        lists.save_rank(&ids, Utc::now()).await.unwrap();
+
+        assert_eq!(lists.load("top_stories").await.unwrap(), ids[..30].to_vec());
+        assert!(lists.load("new_stories").await.unwrap().is_empty());
+    }
+}
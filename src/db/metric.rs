@@ -0,0 +1,118 @@
+//! Measurements of items over time, like their rank, kept in the
+//! `item_metric` table.
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sqlx::sqlite::SqlitePool;
+
+use crate::result::Result;
+
+/// A metric recorded for an item, e.g. its rank at some point in time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ItemMetric {
+    pub item_id: i64,
+    pub metric: String,
+    pub value: i64,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Clone)]
+pub struct Metrics {
+    pool: SqlitePool,
+}
+
+impl Metrics {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    /// Every metric recorded for an item, newest first.
+    pub async fn for_item(&self, item_id: u32) -> Result<Vec<ItemMetric>> {
+        let metrics = sqlx::query_as!(
+            ItemMetric,
+            r#"
+            SELECT
+                *
+            FROM
+                item_metric
+            WHERE
+                item_id = ?1
+            ORDER BY
+                created_at DESC
+            "#,
+            item_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(metrics)
+    }
+
+    /// Drop metrics recorded before `cutoff`.
+    pub async fn delete_before(&self, cutoff: DateTime<Utc>) -> Result<()> {
+        sqlx::query!("DELETE FROM item_metric WHERE created_at < ?1", cutoff)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::list::Lists;
+    use chrono::Duration;
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+    use std::str::FromStr;
+
+    async fn setup() -> (Lists, Metrics) {
+        let options = SqliteConnectOptions::from_str("sqlite::memory:").unwrap();
+        let pool = SqlitePoolOptions::new().connect_lazy_with(options);
+        sqlx::migrate!().run(&pool).await.unwrap();
+        (Lists::new(pool.clone()), Metrics::new(pool))
+    }
+
+    fn values(metrics: Vec<ItemMetric>) -> Vec<(i64, i64)> {
+        metrics
+            .into_iter()
+            .map(|metric| (metric.item_id, metric.value))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn loads_an_items_metrics_newest_first() {
+        let (lists, metrics) = setup().await;
+        let now = Utc::now();
+
+        lists
+            .save_rank(&[40, 41], now - Duration::seconds(1))
+            .await
+            .unwrap();
+        lists.save_rank(&[41, 40], now).await.unwrap();
+
+        let got = metrics.for_item(40).await.unwrap();
+
+        assert_eq!(values(got.clone()), vec![(40, 2), (40, 1)]);
+        assert!(got.iter().all(|metric| metric.metric == "rank"));
+    }
+
+    #[tokio::test]
+    async fn drops_old_metrics() {
+        let (lists, metrics) = setup().await;
+        let now = Utc::now();
+
+        lists
+            .save_rank(&[40], now - Duration::days(100))
+            .await
+            .unwrap();
+        lists.save_rank(&[41, 40], now).await.unwrap();
+
+        metrics
+            .delete_before(now - Duration::days(90))
+            .await
+            .unwrap();
+
+        assert_eq!(values(metrics.for_item(40).await.unwrap()), vec![(40, 2)]);
+        assert_eq!(values(metrics.for_item(41).await.unwrap()), vec![(41, 1)]);
+    }
+}
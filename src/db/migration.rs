@@ -0,0 +1,128 @@
+//! What state the database schema is in, compared to the migrations built
+//! into this binary.
+
+use sqlx::migrate::Migrator;
+use sqlx::sqlite::SqlitePool;
+
+use crate::result::{Error, Result};
+
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// Bring the schema up to date, refusing to touch a database that a newer
+/// binary has migrated past what we know about.
+pub async fn run(pool: &SqlitePool) -> Result<()> {
+    if let Some(version) = unknown(pool).await?.into_iter().max() {
+        return Err(Error::SchemaTooNew {
+            database: version,
+            binary: latest(),
+        });
+    }
+
+    MIGRATOR.run(pool).await?;
+
+    Ok(())
+}
+
+/// Versions of the migrations that have been applied successfully.
+pub async fn applied(pool: &SqlitePool) -> Result<Vec<i64>> {
+    let (exists,): (bool,) = sqlx::query_as(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = '_sqlx_migrations'",
+    )
+    .fetch_one(pool)
+    .await?;
+    if !exists {
+        return Ok(vec![]);
+    }
+
+    let versions = sqlx::query_scalar(
+        "SELECT version FROM _sqlx_migrations WHERE success = 1 ORDER BY version",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(versions)
+}
+
+/// The newest applied migration, if any.
+pub async fn version(pool: &SqlitePool) -> Result<Option<i64>> {
+    Ok(applied(pool).await?.into_iter().max())
+}
+
+/// Versions of the migrations built into this binary that haven't been applied.
+pub async fn pending(pool: &SqlitePool) -> Result<Vec<i64>> {
+    let applied = applied(pool).await?;
+
+    Ok(MIGRATOR
+        .iter()
+        .map(|migration| migration.version)
+        .filter(|version| !applied.contains(version))
+        .collect())
+}
+
+/// Versions applied to the database that this binary doesn't know about.
+async fn unknown(pool: &SqlitePool) -> Result<Vec<i64>> {
+    Ok(applied(pool)
+        .await?
+        .into_iter()
+        .filter(|version| {
+            !MIGRATOR
+                .iter()
+                .any(|migration| migration.version == *version)
+        })
+        .collect())
+}
+
+/// The newest migration built into this binary.
+pub fn latest() -> i64 {
+    MIGRATOR
+        .iter()
+        .map(|migration| migration.version)
+        .max()
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+    use std::str::FromStr;
+
+    fn setup() -> SqlitePool {
+        let options = SqliteConnectOptions::from_str("sqlite::memory:").unwrap();
+        SqlitePoolOptions::new().connect_lazy_with(options)
+    }
+
+    #[tokio::test]
+    async fn migrates_a_fresh_database() {
+        let pool = setup();
+        assert_eq!(version(&pool).await.unwrap(), None);
+
+        run(&pool).await.unwrap();
+
+        assert_eq!(version(&pool).await.unwrap(), Some(latest()));
+        assert!(pending(&pool).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn refuses_a_database_from_a_newer_binary() {
+        let pool = setup();
+        run(&pool).await.unwrap();
+        sqlx::query(
+            "INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time)
+             VALUES (99990101000000, 'from the future', 1, x'00', 0)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let got = run(&pool).await;
+
+        assert!(matches!(
+            got,
+            Err(Error::SchemaTooNew {
+                database: 99990101000000,
+                ..
+            })
+        ));
+    }
+}
@@ -1,2 +1,27 @@
+pub mod bookmark;
+pub mod codec;
+pub mod config;
 pub mod item;
+pub mod list;
+pub mod metric;
+pub mod migration;
+#[cfg(feature = "postgres")]
+mod postgres;
+pub mod quarantine;
+pub mod revision;
+mod sqlite;
+pub mod stats;
+pub mod storage;
+pub mod title_change;
+
+pub use codec::Codec;
 pub use item::Item;
+pub use metric::ItemMetric;
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresStorage;
+pub use quarantine::QuarantinedItem;
+pub use revision::ItemRevision;
+pub use sqlite::SqliteStorage;
+pub use stats::Stats;
+pub use storage::Storage;
+pub use title_change::TitleChange;
@@ -0,0 +1,826 @@
+//! Storage in PostgreSQL, for deployments where several instances write to
+//! one database. Queries are checked at runtime rather than against
+//! `sqlx-data.json`, which only describes the SQLite schema.
+//!
+//! Migrations live in `migrations/postgres` and share version numbers with
+//! the SQLite ones, so schema versions mean the same thing on both.
+
+use std::collections::HashSet;
+
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sqlx::migrate::Migrator;
+use sqlx::postgres::{PgConnection, PgPool, PgPoolOptions};
+
+use crate::db::{
+    item, migration, revision, storage::JobRun, Codec, Item, ItemMetric, ItemRevision,
+    QuarantinedItem, Stats, Storage, TitleChange,
+};
+use crate::result::{Error, Result};
+
+pub static MIGRATOR: Migrator = sqlx::migrate!("migrations/postgres");
+
+#[derive(Clone)]
+pub struct PostgresStorage {
+    pool: PgPool,
+    codec: Codec,
+}
+
+impl PostgresStorage {
+    pub fn connect(url: &str) -> Result<Self> {
+        let pool = PgPoolOptions::new().connect_lazy(url)?;
+        Ok(Self::new(pool))
+    }
+
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            pool,
+            codec: Codec::Plain,
+        }
+    }
+
+    /// Write items' JSON with `codec`. Plain by default.
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    async fn applied_migrations(&self) -> Result<Vec<i64>> {
+        let exists: bool = sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+            .fetch_one(&self.pool)
+            .await?;
+        if !exists {
+            return Ok(vec![]);
+        }
+
+        let versions = sqlx::query_scalar(
+            "SELECT version FROM _sqlx_migrations WHERE success ORDER BY version",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(versions)
+    }
+}
+
+fn ids(ids: &[u32]) -> Vec<i64> {
+    ids.iter().map(|id| *id as i64).collect()
+}
+
+type TitleChangeRow = (
+    i64,
+    String,
+    String,
+    Option<String>,
+    Option<String>,
+    DateTime<Utc>,
+);
+
+fn title_change(row: TitleChangeRow) -> TitleChange {
+    let (item_id, old_title, new_title, old_url, new_url, created_at) = row;
+    TitleChange {
+        item_id,
+        old_title,
+        new_title,
+        old_url,
+        new_url,
+        created_at: created_at.naive_utc(),
+    }
+}
+
+type QuarantinedItemRow = (i64, Option<Vec<u8>>, Option<String>, String, DateTime<Utc>);
+
+fn quarantined_item(row: QuarantinedItemRow) -> QuarantinedItem {
+    let (item_id, original, codec, reason, created_at) = row;
+    QuarantinedItem {
+        item_id,
+        original,
+        codec,
+        reason,
+        created_at: created_at.naive_utc(),
+    }
+}
+
+/// Record a revision if `item`'s content differs from the stored version,
+/// like [`revision::Revisions::record`] does for SQLite, which also says what
+/// happens to a stored version that can't be read. Must run in a
+/// transaction: the item's row stays locked until it ends, so concurrent
+/// saves of the same item can't both take the next revision number.
+async fn record_revision(conn: &mut PgConnection, item: &Item, at: DateTime<Utc>) -> Result<()> {
+    let stored: Option<Item> = sqlx::query_as("SELECT * FROM item WHERE id = $1 FOR UPDATE")
+        .bind(item.id)
+        .fetch_optional(&mut *conn)
+        .await?;
+    let stored = match stored {
+        Some(stored) => stored,
+        None => return Ok(()),
+    };
+    let (before, after) = (stored.json().ok(), item.json()?);
+    if matches!(&before, Some(before) if !revision::changed(before, &after)) {
+        return Ok(());
+    }
+
+    let latest: Option<i64> =
+        sqlx::query_scalar("SELECT MAX(revision) FROM item_revision WHERE item_id = $1")
+            .bind(item.id)
+            .fetch_one(&mut *conn)
+            .await?;
+
+    let mut revisions = vec![];
+    let next = match (latest, before) {
+        (Some(latest), _) => latest + 1,
+        (None, Some(before)) => {
+            revisions.push((1, before, stored.time.unwrap_or(at)));
+            2
+        }
+        (None, None) => 1,
+    };
+    revisions.push((next, after, at));
+
+    for (revision, original, created_at) in revisions {
+        sqlx::query(
+            "INSERT INTO item_revision (item_id, revision, original, created_at)
+             VALUES ($1, $2, $3, $4)",
+        )
+        .bind(item.id)
+        .bind(revision)
+        .bind(original)
+        .bind(created_at)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
+}
+
+#[async_trait]
+impl Storage for PostgresStorage {
+    async fn migrate(&self) -> Result<()> {
+        let applied = self.applied_migrations().await?;
+        let unknown = applied
+            .into_iter()
+            .filter(|version| {
+                !MIGRATOR
+                    .iter()
+                    .any(|migration| migration.version == *version)
+            })
+            .max();
+        if let Some(version) = unknown {
+            return Err(Error::SchemaTooNew {
+                database: version,
+                binary: migration::latest(),
+            });
+        }
+
+        MIGRATOR.run(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn pending_migrations(&self) -> Result<Vec<i64>> {
+        let applied = self.applied_migrations().await?;
+
+        Ok(MIGRATOR
+            .iter()
+            .map(|migration| migration.version)
+            .filter(|version| !applied.contains(version))
+            .collect())
+    }
+
+    async fn ping(&self) -> Result<()> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn stats(&self) -> Result<Stats> {
+        let (item_count, min_item_id, max_item_id) =
+            sqlx::query_as("SELECT COUNT(*), MIN(id), MAX(id) FROM item")
+                .fetch_one(&self.pool)
+                .await?;
+        let backfill_ptr = self.get_config("backfill_ptr").await?;
+
+        Ok(Stats {
+            item_count,
+            min_item_id,
+            max_item_id,
+            backfill_ptr: backfill_ptr.and_then(|ptr| ptr.parse().ok()),
+            schema_version: self.applied_migrations().await?.into_iter().max(),
+        })
+    }
+
+    async fn close(&self) {
+        self.pool.close().await
+    }
+
+    async fn load_item(&self, id: u32) -> Result<Option<Item>> {
+        let item = sqlx::query_as("SELECT * FROM item WHERE id = $1")
+            .bind(id as i64)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(item)
+    }
+
+    async fn load_items(&self, ids: &[u32]) -> Result<Vec<Item>> {
+        let items = sqlx::query_as("SELECT * FROM item WHERE id = ANY($1)")
+            .bind(self::ids(ids))
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(items)
+    }
+
+    async fn items_after(&self, after: i64, limit: u32) -> Result<Vec<Item>> {
+        let items = sqlx::query_as("SELECT * FROM item WHERE id > $1 ORDER BY id LIMIT $2")
+            .bind(after)
+            .bind(limit as i64)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(items)
+    }
+
+    async fn search_items(&self, query: &str, limit: u32, offset: u32) -> Result<Vec<Item>> {
+        let items = sqlx::query_as(
+            r#"
+            SELECT * FROM item
+            WHERE title ILIKE $1 ESCAPE '\'
+            ORDER BY time DESC, id DESC
+            LIMIT $2 OFFSET $3
+            "#,
+        )
+        .bind(item::contains_pattern(query))
+        .bind(limit as i64)
+        .bind(offset as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(items)
+    }
+
+    async fn insert_items(&self, items: &[Item], checkpoint: Option<(&str, &str)>) -> Result<()> {
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await?;
+        for item in items {
+            let item = item.encoded(self.codec)?;
+            record_revision(&mut tx, &item, now).await?;
+            sqlx::query(
+                r#"
+                INSERT INTO item (id, original, codec, item_type, descendants, username, score, title, url, body, time)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+                ON CONFLICT (id) DO UPDATE SET
+                    original = excluded.original,
+                    codec = excluded.codec,
+                    item_type = excluded.item_type,
+                    descendants = excluded.descendants,
+                    username = excluded.username,
+                    score = excluded.score,
+                    title = excluded.title,
+                    url = excluded.url,
+                    body = excluded.body,
+                    time = excluded.time
+                "#,
+            )
+            .bind(item.id)
+            .bind(&item.original)
+            .bind(&item.codec)
+            .bind(&item.item_type)
+            .bind(item.descendants)
+            .bind(&item.username)
+            .bind(item.score)
+            .bind(&item.title)
+            .bind(&item.url)
+            .bind(&item.body)
+            .bind(item.time)
+            .execute(&mut tx)
+            .await?;
+        }
+
+        if let Some((key, value)) = checkpoint {
+            sqlx::query(
+                "INSERT INTO config (key, value) VALUES ($1, $2)
+                 ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+            )
+            .bind(key)
+            .bind(value)
+            .execute(&mut tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn recode_items(&self, items: &[Item], codec: Codec) -> Result<u64> {
+        let mut recoded = 0;
+        let mut tx = self.pool.begin().await?;
+        for item in items {
+            if item.codec()? == codec {
+                continue;
+            }
+
+            let item = item.encoded(codec)?;
+            sqlx::query("UPDATE item SET original = $1, codec = $2 WHERE id = $3")
+                .bind(&item.original)
+                .bind(&item.codec)
+                .bind(item.id)
+                .execute(&mut tx)
+                .await?;
+            recoded += 1;
+        }
+
+        tx.commit().await?;
+        Ok(recoded)
+    }
+
+    async fn item_revisions(&self, item_id: u32) -> Result<Vec<ItemRevision>> {
+        let rows: Vec<(i64, i64, String, DateTime<Utc>)> = sqlx::query_as(
+            "SELECT item_id, revision, original, created_at FROM item_revision
+             WHERE item_id = $1
+             ORDER BY revision ASC",
+        )
+        .bind(item_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(item_id, revision, original, created_at)| ItemRevision {
+                item_id,
+                revision,
+                original,
+                created_at: created_at.naive_utc(),
+            })
+            .collect())
+    }
+
+    async fn list(&self, key: &str) -> Result<Vec<u32>> {
+        let ids: Vec<i64> =
+            sqlx::query_scalar("SELECT item_id FROM list WHERE key = $1 ORDER BY ordering")
+                .bind(key)
+                .fetch_all(&self.pool)
+                .await?;
+
+        Ok(ids.into_iter().map(|id| id as u32).collect())
+    }
+
+    async fn save_rank(&self, top_stories: &[u32], ts: DateTime<Utc>) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        let mut changed = false;
+
+        // Instances sharing the database would otherwise race to refill the
+        // list, one failing on its primary key
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtext('list:top_stories'))")
+            .execute(&mut tx)
+            .await?;
+
+        sqlx::query("DELETE FROM list WHERE key = 'top_stories'")
+            .execute(&mut tx)
+            .await?;
+
+        for (ordering, id) in top_stories.iter().take(30).enumerate() {
+            let id = *id as i64;
+            let ordering = ordering as i64;
+            let rank = ordering + 1;
+
+            sqlx::query(
+                "INSERT INTO list (key, item_id, ordering, created_at)
+                 VALUES ('top_stories', $1, $2, $3)",
+            )
+            .bind(id)
+            .bind(ordering)
+            .bind(ts)
+            .execute(&mut tx)
+            .await?;
+
+            let existing: Option<i64> = sqlx::query_scalar(
+                "SELECT value FROM item_metric
+                 WHERE metric = 'rank' AND item_id = $1
+                 ORDER BY created_at DESC
+                 LIMIT 1",
+            )
+            .bind(id)
+            .fetch_optional(&mut tx)
+            .await?;
+
+            if existing != Some(rank) {
+                changed = true;
+                sqlx::query(
+                    "INSERT INTO item_metric (item_id, metric, created_at, value)
+                     VALUES ($1, 'rank', $2, $3)",
+                )
+                .bind(id)
+                .bind(ts)
+                .bind(rank)
+                .execute(&mut tx)
+                .await?;
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(changed)
+    }
+
+    async fn item_metrics(&self, item_id: u32) -> Result<Vec<ItemMetric>> {
+        let rows: Vec<(i64, String, i64, DateTime<Utc>)> = sqlx::query_as(
+            "SELECT item_id, metric, value, created_at FROM item_metric
+             WHERE item_id = $1
+             ORDER BY created_at DESC",
+        )
+        .bind(item_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(item_id, metric, value, created_at)| ItemMetric {
+                item_id,
+                metric,
+                value,
+                created_at: created_at.naive_utc(),
+            })
+            .collect())
+    }
+
+    async fn delete_metrics_before(&self, cutoff: DateTime<Utc>) -> Result<()> {
+        sqlx::query("DELETE FROM item_metric WHERE created_at < $1")
+            .bind(cutoff)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn bookmarked_ids(&self) -> Result<Vec<u32>> {
+        let ids: Vec<i64> =
+            sqlx::query_scalar("SELECT item_id FROM bookmarked_item ORDER BY created_at DESC")
+                .fetch_all(&self.pool)
+                .await?;
+
+        Ok(ids.into_iter().map(|id| id as u32).collect())
+    }
+
+    async fn user_bookmarked_ids(&self, user_id: &str) -> Result<Vec<u32>> {
+        let ids: Vec<i64> = sqlx::query_scalar(
+            "SELECT item_id FROM bookmarked_item WHERE user_id = $1 ORDER BY created_at DESC",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(ids.into_iter().map(|id| id as u32).collect())
+    }
+
+    async fn bookmarked_among(&self, ids: &[u32]) -> Result<HashSet<u32>> {
+        let bookmarked: Vec<i64> =
+            sqlx::query_scalar("SELECT item_id FROM bookmarked_item WHERE item_id = ANY($1)")
+                .bind(self::ids(ids))
+                .fetch_all(&self.pool)
+                .await?;
+
+        Ok(bookmarked.into_iter().map(|id| id as u32).collect())
+    }
+
+    async fn add_bookmark(&self, item_id: u32, user_id: &str, at: DateTime<Utc>) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO bookmarked_item (item_id, user_id, created_at) VALUES ($1, $2, $3)",
+        )
+        .bind(item_id as i64)
+        .bind(user_id)
+        .bind(at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn remove_bookmark(&self, item_id: u32, user_id: &str) -> Result<()> {
+        sqlx::query("DELETE FROM bookmarked_item WHERE item_id = $1 AND user_id = $2")
+            .bind(item_id as i64)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn record_title_change(&self, change: &TitleChange) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO title_change (item_id, old_title, new_title, old_url, new_url, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(change.item_id)
+        .bind(&change.old_title)
+        .bind(&change.new_title)
+        .bind(&change.old_url)
+        .bind(&change.new_url)
+        .bind(DateTime::<Utc>::from_naive_utc_and_offset(
+            change.created_at,
+            Utc,
+        ))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn title_changes(&self, item_id: u32) -> Result<Vec<TitleChange>> {
+        let rows = sqlx::query_as(
+            "SELECT item_id, old_title, new_title, old_url, new_url, created_at FROM title_change
+             WHERE item_id = $1
+             ORDER BY created_at ASC",
+        )
+        .bind(item_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(title_change).collect())
+    }
+
+    async fn recent_title_changes(&self, limit: u32) -> Result<Vec<TitleChange>> {
+        let rows = sqlx::query_as(
+            "SELECT item_id, old_title, new_title, old_url, new_url, created_at FROM title_change
+             ORDER BY created_at DESC
+             LIMIT $1",
+        )
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(title_change).collect())
+    }
+
+    async fn quarantine_item(&self, item: &Item, reason: &str, at: DateTime<Utc>) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO quarantined_item (item_id, original, codec, reason, created_at)
+             VALUES ($1, $2, $3, $4, $5)
+             ON CONFLICT (item_id) DO UPDATE SET
+                original = EXCLUDED.original,
+                codec = EXCLUDED.codec,
+                reason = EXCLUDED.reason,
+                created_at = EXCLUDED.created_at",
+        )
+        .bind(item.id)
+        .bind(&item.original)
+        .bind(&item.codec)
+        .bind(reason)
+        .bind(at)
+        .execute(&mut tx)
+        .await?;
+        sqlx::query("DELETE FROM item WHERE id = $1")
+            .bind(item.id)
+            .execute(&mut tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn quarantined_items(&self, limit: u32) -> Result<Vec<QuarantinedItem>> {
+        let rows = sqlx::query_as(
+            "SELECT item_id, original, codec, reason, created_at FROM quarantined_item
+             ORDER BY created_at DESC
+             LIMIT $1",
+        )
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(quarantined_item).collect())
+    }
+
+    async fn get_config(&self, key: &str) -> Result<Option<String>> {
+        let value = sqlx::query_scalar("SELECT value FROM config WHERE key = $1")
+            .bind(key)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(value)
+    }
+
+    async fn set_config(&self, key: &str, value: &str) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO config (key, value) VALUES ($1, $2)
+             ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+        )
+        .bind(key)
+        .bind(value)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn record_job_run(
+        &self,
+        name: &str,
+        at: DateTime<Utc>,
+        duration_ms: i64,
+        error: Option<&str>,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO job_run (name, last_run_at, last_duration_ms, last_error, last_success_at)
+            VALUES ($1, $2, $3, $4, CASE WHEN $4::TEXT IS NULL THEN $2 END)
+            ON CONFLICT (name) DO UPDATE SET
+                last_run_at = excluded.last_run_at,
+                last_duration_ms = excluded.last_duration_ms,
+                last_error = excluded.last_error,
+                last_success_at = COALESCE(excluded.last_success_at, job_run.last_success_at)
+            "#,
+        )
+        .bind(name)
+        .bind(at)
+        .bind(duration_ms)
+        .bind(error)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn job_runs(&self) -> Result<Vec<JobRun>> {
+        type Row = (
+            String,
+            DateTime<Utc>,
+            i64,
+            Option<String>,
+            Option<DateTime<Utc>>,
+        );
+        let rows: Vec<Row> = sqlx::query_as(
+            "SELECT name, last_run_at, last_duration_ms, last_error, last_success_at
+             FROM job_run
+             ORDER BY name",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(
+                |(name, last_run_at, last_duration_ms, last_error, last_success_at)| JobRun {
+                    name,
+                    last_run_at: last_run_at.naive_utc(),
+                    last_duration_ms,
+                    last_error,
+                    last_success_at: last_success_at.map(|at| at.naive_utc()),
+                },
+            )
+            .collect())
+    }
+
+    async fn last_job_success(&self) -> Result<Option<NaiveDateTime>> {
+        let last_success: Option<DateTime<Utc>> =
+            sqlx::query_scalar("SELECT MAX(last_success_at) FROM job_run")
+                .fetch_one(&self.pool)
+                .await?;
+
+        Ok(last_success.map(|at| at.naive_utc()))
+    }
+}
+
+/// These need a PostgreSQL server. Each test works in a schema of its own in
+/// the database at `TEST_POSTGRES_URL`, by default `postgres://localhost/twhn_test`.
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::storage::conformance;
+    use sqlx::Executor;
+
+    async fn setup(name: &str) -> PostgresStorage {
+        let url = std::env::var("TEST_POSTGRES_URL")
+            .unwrap_or_else(|_| "postgres://localhost/twhn_test".into());
+        let schema = format!("test_{}", name);
+
+        let pool = PgPoolOptions::new().connect(&url).await.unwrap();
+        pool.execute(format!("DROP SCHEMA IF EXISTS {} CASCADE", schema).as_str())
+            .await
+            .unwrap();
+        pool.execute(format!("CREATE SCHEMA {}", schema).as_str())
+            .await
+            .unwrap();
+        pool.close().await;
+
+        let pool = PgPoolOptions::new()
+            .after_connect(move |conn| {
+                let search_path = format!("SET search_path TO {}", schema);
+                Box::pin(async move {
+                    conn.execute(search_path.as_str()).await?;
+                    Ok(())
+                })
+            })
+            .connect(&url)
+            .await
+            .unwrap();
+        let storage = PostgresStorage::new(pool);
+        storage.migrate().await.unwrap();
+        storage
+    }
+
+    #[tokio::test]
+    async fn stores_items() {
+        conformance::stores_items(&setup("stores_items").await).await;
+    }
+
+    #[tokio::test]
+    async fn searches_titles() {
+        conformance::searches_titles(&setup("searches_titles").await).await;
+    }
+
+    #[tokio::test]
+    async fn recodes_items() {
+        conformance::recodes_items(&setup("recodes_items").await).await;
+    }
+
+    #[tokio::test]
+    async fn keeps_revisions() {
+        conformance::keeps_revisions(&setup("keeps_revisions").await).await;
+    }
+
+    #[tokio::test]
+    async fn replaces_unreadable_items() {
+        conformance::replaces_unreadable_items(&setup("replaces_unreadable_items").await).await;
+    }
+
+    #[tokio::test]
+    async fn saves_ranks() {
+        conformance::saves_ranks(&setup("saves_ranks").await).await;
+    }
+
+    #[tokio::test]
+    async fn keeps_bookmarks() {
+        conformance::keeps_bookmarks(&setup("keeps_bookmarks").await).await;
+    }
+
+    #[tokio::test]
+    async fn records_title_changes() {
+        conformance::records_title_changes(&setup("records_title_changes").await).await;
+    }
+
+    #[tokio::test]
+    async fn quarantines_items() {
+        conformance::quarantines_items(&setup("quarantines_items").await).await;
+    }
+
+    #[tokio::test]
+    async fn records_job_runs() {
+        conformance::records_job_runs(&setup("records_job_runs").await).await;
+    }
+
+    #[tokio::test]
+    async fn numbers_concurrent_revisions() {
+        let storage = setup("numbers_concurrent_revisions").await;
+        storage
+            .insert_items(&[conformance::story(1, "Hi")], None)
+            .await
+            .unwrap();
+
+        let titles = (0..8).map(|n| format!("Hi {}", n)).collect::<Vec<_>>();
+        let saves = titles.iter().map(|title| {
+            let storage = &storage;
+            async move {
+                storage
+                    .insert_items(&[conformance::story(1, title)], None)
+                    .await
+            }
+        });
+        for saved in futures::future::join_all(saves).await {
+            saved.unwrap();
+        }
+
+        let numbers = storage
+            .item_revisions(1)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|revision| revision.revision)
+            .collect::<Vec<_>>();
+        assert_eq!(numbers, (1..=9).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn saves_ranks_concurrently() {
+        let storage = setup("saves_ranks_concurrently").await;
+        let now = Utc::now();
+
+        let saves = (0..8).map(|_| storage.save_rank(&[40, 41], now));
+        for saved in futures::future::join_all(saves).await {
+            saved.unwrap();
+        }
+
+        assert_eq!(storage.list("top_stories").await.unwrap(), vec![40, 41]);
+    }
+
+    #[tokio::test]
+    async fn migrates_once() {
+        let storage = setup("migrates_once").await;
+        storage.migrate().await.unwrap();
+
+        assert!(storage.pending_migrations().await.unwrap().is_empty());
+        assert_eq!(
+            storage.stats().await.unwrap().schema_version,
+            Some(migration::latest())
+        );
+    }
+}
@@ -0,0 +1,148 @@
+//! Items whose stored record couldn't be read back, moved out of `item` into
+//! `quarantined_item` so they're fetched again instead of failing every read.
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sqlx::sqlite::SqlitePool;
+
+use crate::db::{Codec, Item};
+use crate::result::Result;
+
+/// A stored item set aside, as it was, with why it couldn't be read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuarantinedItem {
+    pub item_id: i64,
+    pub original: Option<Vec<u8>>,
+    pub codec: Option<String>,
+    pub reason: String,
+    pub created_at: NaiveDateTime,
+}
+
+impl QuarantinedItem {
+    /// The stored JSON, if it still decodes.
+    pub fn json(&self) -> Option<String> {
+        let original = self.original.as_deref()?;
+        Codec::from_marker(self.codec.as_deref())
+            .and_then(|codec| codec.decode(original))
+            .ok()
+    }
+}
+
+#[derive(Clone)]
+pub struct Quarantine {
+    pool: SqlitePool,
+}
+
+impl Quarantine {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    /// Move `item` out of the item table. Quarantining an item again replaces
+    /// the earlier record.
+    pub async fn add(&self, item: &Item, reason: &str, at: DateTime<Utc>) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query!(
+            r#"
+            INSERT OR REPLACE INTO quarantined_item (item_id, original, codec, reason, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            "#,
+            item.id,
+            item.original,
+            item.codec,
+            reason,
+            at
+        )
+        .execute(&mut tx)
+        .await?;
+        sqlx::query!("DELETE FROM item WHERE id = ?1", item.id)
+            .execute(&mut tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// The latest quarantined items, newest first.
+    pub async fn list(&self, limit: u32) -> Result<Vec<QuarantinedItem>> {
+        let items = sqlx::query_as!(
+            QuarantinedItem,
+            r#"
+            SELECT
+                *
+            FROM
+                quarantined_item
+            ORDER BY
+                created_at DESC
+            LIMIT ?1
+            "#,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(items)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Duration;
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+    use std::str::FromStr;
+
+    async fn setup() -> (SqlitePool, Quarantine) {
+        let options = SqliteConnectOptions::from_str("sqlite::memory:").unwrap();
+        let pool = SqlitePoolOptions::new().connect_lazy_with(options);
+        sqlx::migrate!().run(&pool).await.unwrap();
+        (pool.clone(), Quarantine::new(pool))
+    }
+
+    async fn insert_corrupt(pool: &SqlitePool, id: i64) -> Item {
+        sqlx::query("INSERT INTO item (id, original) VALUES (?1, '{not json')")
+            .bind(id)
+            .execute(pool)
+            .await
+            .unwrap();
+        Item::load(id as u32).fetch_one(pool).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn moves_items_out_of_the_item_table() {
+        let (pool, quarantine) = setup().await;
+        let item = insert_corrupt(&pool, 1).await;
+
+        quarantine.add(&item, "bad json", Utc::now()).await.unwrap();
+
+        assert!(Item::load(1).fetch_optional(&pool).await.unwrap().is_none());
+        let got = quarantine.list(10).await.unwrap();
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].item_id, 1);
+        assert_eq!(got[0].original.as_deref(), Some(&b"{not json"[..]));
+        assert_eq!(got[0].reason, "bad json");
+        assert_eq!(got[0].json().as_deref(), Some("{not json"));
+    }
+
+    #[tokio::test]
+    async fn lists_newest_first() {
+        let (pool, quarantine) = setup().await;
+        let now = Utc::now();
+
+        for (id, age) in [(1, 3), (2, 1), (3, 2)] {
+            let item = insert_corrupt(&pool, id).await;
+            quarantine
+                .add(&item, "bad json", now - Duration::seconds(age))
+                .await
+                .unwrap();
+        }
+
+        let got = quarantine
+            .list(2)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|item| item.item_id)
+            .collect::<Vec<_>>();
+        assert_eq!(got, vec![2, 3]);
+    }
+}
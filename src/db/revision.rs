@@ -0,0 +1,280 @@
+//! Earlier versions of items, kept in the `item_revision` table.
+//!
+//! Nothing is recorded for an item until its content changes. The first
+//! change records both the stored version, as revision 1, and the new one, so
+//! an item with revisions always has its current content as the latest.
+//! Scores and comment counts change all the time, so they don't count as
+//! content.
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde_json::{Map, Value};
+use sqlx::sqlite::{SqliteConnection, SqlitePool};
+
+use crate::db::Item;
+use crate::result::Result;
+
+/// Fields that change without the item's content changing.
+const VOLATILE: &[&str] = &["score", "descendants"];
+
+/// A version of an item, as HN sent it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ItemRevision {
+    pub item_id: i64,
+    pub revision: i64,
+    pub original: String,
+    /// When this version was first seen. For revision 1 that's when the item
+    /// was posted, as earlier versions weren't kept.
+    pub created_at: NaiveDateTime,
+}
+
+/// A field that differs between two revisions. `None` means the field is
+/// missing from that revision.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub field: String,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+fn content(original: &str) -> Map<String, Value> {
+    let mut fields = match serde_json::from_str(original) {
+        Ok(Value::Object(fields)) => fields,
+        _ => Map::new(),
+    };
+    for field in VOLATILE {
+        fields.remove(*field);
+    }
+    fields
+}
+
+/// Whether the content differs between two versions of an item.
+pub fn changed(before: &str, after: &str) -> bool {
+    content(before) != content(after)
+}
+
+/// The content fields that differ between two revisions, by name.
+pub fn diff(from: &ItemRevision, to: &ItemRevision) -> Vec<Change> {
+    let before = content(&from.original);
+    let after = content(&to.original);
+
+    let mut fields = before.keys().chain(after.keys()).collect::<Vec<_>>();
+    fields.sort_unstable();
+    fields.dedup();
+
+    fields
+        .into_iter()
+        .filter(|field| before.get(*field) != after.get(*field))
+        .map(|field| Change {
+            field: field.clone(),
+            before: before.get(field).cloned(),
+            after: after.get(field).cloned(),
+        })
+        .collect()
+}
+
+#[derive(Clone)]
+pub struct Revisions {
+    pool: SqlitePool,
+}
+
+impl Revisions {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    /// Every revision of an item, oldest first.
+    pub async fn for_item(&self, item_id: u32) -> Result<Vec<ItemRevision>> {
+        let revisions = sqlx::query_as!(
+            ItemRevision,
+            r#"
+            SELECT
+                *
+            FROM
+                item_revision
+            WHERE
+                item_id = ?1
+            ORDER BY
+                revision ASC
+            "#,
+            item_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(revisions)
+    }
+
+    /// Record a revision if `item`'s content differs from the stored version.
+    /// Call before storing `item`, in the same transaction. A stored version
+    /// that can't be read counts as different, and isn't kept.
+    pub async fn record(conn: &mut SqliteConnection, item: &Item, at: DateTime<Utc>) -> Result<()> {
+        let stored = match Item::load(item.id as u32)
+            .fetch_optional(&mut *conn)
+            .await?
+        {
+            Some(stored) => stored,
+            None => return Ok(()),
+        };
+        let (before, after) = (stored.json().ok(), item.json()?);
+        if matches!(&before, Some(before) if !changed(before, &after)) {
+            return Ok(());
+        }
+
+        let latest = sqlx::query_scalar!(
+            r#"SELECT MAX(revision) AS "latest?: i64" FROM item_revision WHERE item_id = ?1"#,
+            item.id
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+
+        let revision = match (latest, before) {
+            (Some(latest), _) => latest + 1,
+            (None, Some(before)) => {
+                let posted_at = stored.time.unwrap_or(at);
+                Self::insert(conn, item.id, 1, &before, posted_at).await?;
+                2
+            }
+            (None, None) => 1,
+        };
+        Self::insert(conn, item.id, revision, &after, at).await
+    }
+
+    async fn insert(
+        conn: &mut SqliteConnection,
+        item_id: i64,
+        revision: i64,
+        original: &str,
+        at: DateTime<Utc>,
+    ) -> Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO item_revision (item_id, revision, original, created_at)
+            VALUES (?1, ?2, ?3, ?4)
+            "#,
+            item_id,
+            revision,
+            original,
+            at
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+    use std::str::FromStr;
+
+    async fn setup() -> (SqlitePool, Revisions) {
+        let options = SqliteConnectOptions::from_str("sqlite::memory:").unwrap();
+        let pool = SqlitePoolOptions::new().connect_lazy_with(options);
+        sqlx::migrate!().run(&pool).await.unwrap();
+        (pool.clone(), Revisions::new(pool))
+    }
+
+    fn story(title: &str, score: u32) -> Item {
+        let json = json!({
+            "type": "story",
+            "id": 1,
+            "by": "dan",
+            "time": 1175714200,
+            "title": title,
+            "score": score,
+            "descendants": 0,
+        });
+        serde_json::from_value::<crate::domain::Item>(json)
+            .unwrap()
+            .try_into()
+            .unwrap()
+    }
+
+    async fn store(pool: &SqlitePool, item: &Item) {
+        let mut tx = pool.begin().await.unwrap();
+        Revisions::record(&mut tx, item, Utc::now()).await.unwrap();
+        item.insert().execute(&mut tx).await.unwrap();
+        tx.commit().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn records_nothing_until_content_changes() {
+        let (pool, revisions) = setup().await;
+
+        store(&pool, &story("Hi", 1)).await;
+        store(&pool, &story("Hi", 5)).await;
+
+        assert!(revisions.for_item(1).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn records_the_stored_and_new_versions() {
+        let (pool, revisions) = setup().await;
+
+        store(&pool, &story("Hi", 1)).await;
+        store(&pool, &story("Hello", 2)).await;
+        store(&pool, &story("Hello", 3)).await;
+        store(&pool, &story("Hello there", 3)).await;
+
+        let got = revisions
+            .for_item(1)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|revision| {
+                (
+                    revision.revision,
+                    content(&revision.original)["title"].clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let want = vec![
+            (1, json!("Hi")),
+            (2, json!("Hello")),
+            (3, json!("Hello there")),
+        ];
+        assert_eq!(got, want);
+    }
+
+    #[tokio::test]
+    async fn diffs_content_fields() {
+        let revision = |revision, original: Value| ItemRevision {
+            item_id: 1,
+            revision,
+            original: original.to_string(),
+            created_at: Utc::now().naive_utc(),
+        };
+        let from = revision(
+            1,
+            json!({"type": "comment", "id": 1, "by": "dan", "text": "hi", "parent": 2}),
+        );
+        let to = revision(
+            2,
+            json!({"type": "comment", "id": 1, "deleted": true, "parent": 2}),
+        );
+
+        let want = vec![
+            Change {
+                field: "by".into(),
+                before: Some(json!("dan")),
+                after: None,
+            },
+            Change {
+                field: "deleted".into(),
+                before: None,
+                after: Some(json!(true)),
+            },
+            Change {
+                field: "text".into(),
+                before: Some(json!("hi")),
+                after: None,
+            },
+        ];
+        assert_eq!(diff(&from, &to), want);
+        assert!(diff(&from, &from).is_empty());
+    }
+}
@@ -0,0 +1,328 @@
+use std::collections::HashSet;
+
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sqlx::sqlite::SqlitePool;
+
+use crate::db::{
+    bookmark::Bookmarks, codec::Codec, config::Settings, list::Lists, metric::Metrics, migration,
+    quarantine::Quarantine, revision::Revisions, storage::JobRun, title_change::TitleChanges, Item,
+    ItemMetric, ItemRevision, QuarantinedItem, Stats, Storage, TitleChange,
+};
+use crate::result::Result;
+
+/// Storage in a SQLite file, or in memory for tests. Queries live in the
+/// repositories next to this module; this mostly hands off to them.
+#[derive(Clone)]
+pub struct SqliteStorage {
+    pool: SqlitePool,
+    codec: Codec,
+    lists: Lists,
+    metrics: Metrics,
+    bookmarks: Bookmarks,
+    settings: Settings,
+    revisions: Revisions,
+    title_changes: TitleChanges,
+    quarantine: Quarantine,
+}
+
+impl SqliteStorage {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self {
+            codec: Codec::Plain,
+            lists: Lists::new(pool.clone()),
+            metrics: Metrics::new(pool.clone()),
+            bookmarks: Bookmarks::new(pool.clone()),
+            settings: Settings::new(pool.clone()),
+            revisions: Revisions::new(pool.clone()),
+            title_changes: TitleChanges::new(pool.clone()),
+            quarantine: Quarantine::new(pool.clone()),
+            pool,
+        }
+    }
+
+    /// Write items' JSON with `codec`. Plain by default.
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn migrate(&self) -> Result<()> {
+        migration::run(&self.pool).await
+    }
+
+    async fn pending_migrations(&self) -> Result<Vec<i64>> {
+        migration::pending(&self.pool).await
+    }
+
+    async fn ping(&self) -> Result<()> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn stats(&self) -> Result<Stats> {
+        Stats::load(&self.pool).await
+    }
+
+    async fn close(&self) {
+        self.pool.close().await
+    }
+
+    async fn load_item(&self, id: u32) -> Result<Option<Item>> {
+        Ok(Item::load(id).fetch_optional(&self.pool).await?)
+    }
+
+    async fn load_items(&self, ids: &[u32]) -> Result<Vec<Item>> {
+        Ok(Item::load_many(ids).fetch_all(&self.pool).await?)
+    }
+
+    async fn items_after(&self, after: i64, limit: u32) -> Result<Vec<Item>> {
+        Ok(Item::page(after, limit).fetch_all(&self.pool).await?)
+    }
+
+    async fn search_items(&self, query: &str, limit: u32, offset: u32) -> Result<Vec<Item>> {
+        Ok(Item::search(query, limit, offset)
+            .fetch_all(&self.pool)
+            .await?)
+    }
+
+    async fn insert_items(&self, items: &[Item], checkpoint: Option<(&str, &str)>) -> Result<()> {
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await?;
+        for item in items {
+            let item = item.encoded(self.codec)?;
+            Revisions::record(&mut tx, &item, now).await?;
+            item.insert().execute(&mut tx).await?;
+        }
+
+        if let Some((key, value)) = checkpoint {
+            Settings::upsert(&mut tx, key, value).await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn recode_items(&self, items: &[Item], codec: Codec) -> Result<u64> {
+        let mut recoded = 0;
+        let mut tx = self.pool.begin().await?;
+        for item in items {
+            if item.codec()? == codec {
+                continue;
+            }
+
+            let item = item.encoded(codec)?;
+            sqlx::query!(
+                r#"
+                UPDATE item
+                SET original = CASE WHEN ?2 IS NULL THEN CAST(?1 AS TEXT) ELSE ?1 END, codec = ?2
+                WHERE id = ?3
+                "#,
+                item.original,
+                item.codec,
+                item.id
+            )
+            .execute(&mut tx)
+            .await?;
+            recoded += 1;
+        }
+
+        tx.commit().await?;
+        Ok(recoded)
+    }
+
+    async fn item_revisions(&self, item_id: u32) -> Result<Vec<ItemRevision>> {
+        self.revisions.for_item(item_id).await
+    }
+
+    async fn list(&self, key: &str) -> Result<Vec<u32>> {
+        self.lists.load(key).await
+    }
+
+    async fn save_rank(&self, top_stories: &[u32], at: DateTime<Utc>) -> Result<bool> {
+        self.lists.save_rank(top_stories, at).await
+    }
+
+    async fn item_metrics(&self, item_id: u32) -> Result<Vec<ItemMetric>> {
+        self.metrics.for_item(item_id).await
+    }
+
+    async fn delete_metrics_before(&self, cutoff: DateTime<Utc>) -> Result<()> {
+        self.metrics.delete_before(cutoff).await
+    }
+
+    async fn bookmarked_ids(&self) -> Result<Vec<u32>> {
+        self.bookmarks.ids().await
+    }
+
+    async fn user_bookmarked_ids(&self, user_id: &str) -> Result<Vec<u32>> {
+        self.bookmarks.ids_for(user_id).await
+    }
+
+    async fn bookmarked_among(&self, ids: &[u32]) -> Result<HashSet<u32>> {
+        self.bookmarks.among(ids).await
+    }
+
+    async fn add_bookmark(&self, item_id: u32, user_id: &str, at: DateTime<Utc>) -> Result<()> {
+        self.bookmarks.add(item_id, user_id, at).await
+    }
+
+    async fn remove_bookmark(&self, item_id: u32, user_id: &str) -> Result<()> {
+        self.bookmarks.remove(item_id, user_id).await
+    }
+
+    async fn record_title_change(&self, change: &TitleChange) -> Result<()> {
+        self.title_changes.record(change).await
+    }
+
+    async fn title_changes(&self, item_id: u32) -> Result<Vec<TitleChange>> {
+        self.title_changes.for_item(item_id).await
+    }
+
+    async fn recent_title_changes(&self, limit: u32) -> Result<Vec<TitleChange>> {
+        self.title_changes.recent(limit).await
+    }
+
+    async fn quarantine_item(&self, item: &Item, reason: &str, at: DateTime<Utc>) -> Result<()> {
+        self.quarantine.add(item, reason, at).await
+    }
+
+    async fn quarantined_items(&self, limit: u32) -> Result<Vec<QuarantinedItem>> {
+        self.quarantine.list(limit).await
+    }
+
+    async fn get_config(&self, key: &str) -> Result<Option<String>> {
+        self.settings.get(key).await
+    }
+
+    async fn set_config(&self, key: &str, value: &str) -> Result<()> {
+        self.settings.set(key, value).await
+    }
+
+    async fn record_job_run(
+        &self,
+        name: &str,
+        at: DateTime<Utc>,
+        duration_ms: i64,
+        error: Option<&str>,
+    ) -> Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO job_run (name, last_run_at, last_duration_ms, last_error, last_success_at)
+            VALUES (?1, ?2, ?3, ?4, CASE WHEN ?4 IS NULL THEN ?2 END)
+            ON CONFLICT (name) DO UPDATE SET
+                last_run_at = excluded.last_run_at,
+                last_duration_ms = excluded.last_duration_ms,
+                last_error = excluded.last_error,
+                last_success_at = COALESCE(excluded.last_success_at, job_run.last_success_at)
+            "#,
+            name,
+            at,
+            duration_ms,
+            error,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn job_runs(&self) -> Result<Vec<JobRun>> {
+        let runs = sqlx::query_as!(
+            JobRun,
+            r#"
+            SELECT
+                *
+            FROM
+                job_run
+            ORDER BY
+                name ASC
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(runs)
+    }
+
+    async fn last_job_success(&self) -> Result<Option<NaiveDateTime>> {
+        let last_success = sqlx::query_scalar("SELECT MAX(last_success_at) FROM job_run")
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(last_success)
+    }
+
+    fn sqlite(&self) -> Option<&SqlitePool> {
+        Some(&self.pool)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::storage::conformance;
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+    use std::str::FromStr;
+
+    async fn setup() -> SqliteStorage {
+        let options = SqliteConnectOptions::from_str("sqlite::memory:").unwrap();
+        let pool = SqlitePoolOptions::new().connect_lazy_with(options);
+        let storage = SqliteStorage::new(pool);
+        storage.migrate().await.unwrap();
+        storage
+    }
+
+    #[tokio::test]
+    async fn stores_items() {
+        conformance::stores_items(&setup().await).await;
+    }
+
+    #[tokio::test]
+    async fn searches_titles() {
+        conformance::searches_titles(&setup().await).await;
+    }
+
+    #[tokio::test]
+    async fn recodes_items() {
+        conformance::recodes_items(&setup().await).await;
+    }
+
+    #[tokio::test]
+    async fn keeps_revisions() {
+        conformance::keeps_revisions(&setup().await).await;
+    }
+
+    #[tokio::test]
+    async fn replaces_unreadable_items() {
+        conformance::replaces_unreadable_items(&setup().await).await;
+    }
+
+    #[tokio::test]
+    async fn saves_ranks() {
+        conformance::saves_ranks(&setup().await).await;
+    }
+
+    #[tokio::test]
+    async fn keeps_bookmarks() {
+        conformance::keeps_bookmarks(&setup().await).await;
+    }
+
+    #[tokio::test]
+    async fn records_title_changes() {
+        conformance::records_title_changes(&setup().await).await;
+    }
+
+    #[tokio::test]
+    async fn quarantines_items() {
+        conformance::quarantines_items(&setup().await).await;
+    }
+
+    #[tokio::test]
+    async fn records_job_runs() {
+        conformance::records_job_runs(&setup().await).await;
+    }
+}
@@ -0,0 +1,51 @@
+use serde::Serialize;
+use sqlx::sqlite::SqlitePool;
+
+use crate::db::migration;
+use crate::result::Result;
+
+/// How much is in the database.
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    pub item_count: i64,
+    pub min_item_id: Option<i64>,
+    pub max_item_id: Option<i64>,
+    pub backfill_ptr: Option<i64>,
+    pub schema_version: Option<i64>,
+}
+
+impl Stats {
+    pub async fn load(pool: &SqlitePool) -> Result<Self> {
+        let (item_count, min_item_id, max_item_id) = sqlx::query_as(
+            r#"
+            SELECT 
+                COUNT(*), MIN(id), MAX(id)
+            FROM 
+                item
+            "#,
+        )
+        .fetch_one(pool)
+        .await?;
+
+        let backfill_ptr: Option<String> = sqlx::query_scalar(
+            r#"
+            SELECT 
+                value 
+            FROM 
+                config
+            WHERE 
+                key='backfill_ptr'
+            "#,
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(Self {
+            item_count,
+            min_item_id,
+            max_item_id,
+            backfill_ptr: backfill_ptr.and_then(|ptr| ptr.parse().ok()),
+            schema_version: migration::version(pool).await?,
+        })
+    }
+}
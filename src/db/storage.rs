@@ -0,0 +1,460 @@
+//! Where everything is persisted. SQLite is the default; PostgreSQL, behind
+//! the `postgres` feature, suits shared deployments with several writers.
+//! The backend is picked by the scheme of `DATABASE_URL`.
+
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+
+use crate::db::{
+    Codec, Item, ItemMetric, ItemRevision, QuarantinedItem, SqliteStorage, Stats, TitleChange,
+};
+use crate::result::{Error, Result};
+
+/// How a background job last went.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobRun {
+    pub name: String,
+    pub last_run_at: NaiveDateTime,
+    pub last_duration_ms: i64,
+    pub last_error: Option<String>,
+    pub last_success_at: Option<NaiveDateTime>,
+}
+
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Bring the schema up to date. See [`crate::db::migration::run`].
+    async fn migrate(&self) -> Result<()>;
+
+    /// Versions of the migrations built into this binary that haven't been applied.
+    async fn pending_migrations(&self) -> Result<Vec<i64>>;
+
+    /// Check the database answers at all.
+    async fn ping(&self) -> Result<()>;
+
+    async fn stats(&self) -> Result<Stats>;
+
+    /// Wait for connections to be returned and close them.
+    async fn close(&self);
+
+    async fn load_item(&self, id: u32) -> Result<Option<Item>>;
+
+    /// Whichever of `ids` are stored, in no particular order.
+    async fn load_items(&self, ids: &[u32]) -> Result<Vec<Item>>;
+
+    /// Up to `limit` items with ids above `after`, in id order, for walking
+    /// the whole table.
+    async fn items_after(&self, after: i64, limit: u32) -> Result<Vec<Item>>;
+
+    /// Up to `limit` stories and jobs whose title contains `query`, ignoring
+    /// case, newest first, after skipping `offset`.
+    async fn search_items(&self, query: &str, limit: u32, offset: u32) -> Result<Vec<Item>>;
+
+    /// Insert or replace `items` in one transaction, recording a revision of
+    /// any whose content changed. A `checkpoint` config key and value are
+    /// saved in the same transaction, so progress recorded alongside the
+    /// items can't disagree with them.
+    async fn insert_items(&self, items: &[Item], checkpoint: Option<(&str, &str)>) -> Result<()>;
+
+    /// Rewrite the stored `original` of `items` with `codec`, leaving the
+    /// rest of each row alone. Returns how many weren't in `codec` already.
+    async fn recode_items(&self, items: &[Item], codec: Codec) -> Result<u64>;
+
+    /// Every revision of an item, oldest first. Empty if its content never
+    /// changed. See [`crate::db::revision`].
+    async fn item_revisions(&self, item_id: u32) -> Result<Vec<ItemRevision>>;
+
+    /// The item ids on a list, e.g. `top_stories`, in order.
+    async fn list(&self, key: &str) -> Result<Vec<u32>>;
+
+    /// Replace the top stories list and record the rank of every story whose
+    /// rank changed. Returns whether any did.
+    async fn save_rank(&self, top_stories: &[u32], at: DateTime<Utc>) -> Result<bool>;
+
+    /// Every metric recorded for an item, newest first.
+    async fn item_metrics(&self, item_id: u32) -> Result<Vec<ItemMetric>>;
+
+    /// Drop metrics recorded before `cutoff`.
+    async fn delete_metrics_before(&self, cutoff: DateTime<Utc>) -> Result<()>;
+
+    /// Bookmarked item ids, most recently bookmarked first.
+    async fn bookmarked_ids(&self) -> Result<Vec<u32>>;
+
+    /// Item ids `user_id` bookmarked, most recently bookmarked first.
+    async fn user_bookmarked_ids(&self, user_id: &str) -> Result<Vec<u32>>;
+
+    /// Which of `ids` are bookmarked.
+    async fn bookmarked_among(&self, ids: &[u32]) -> Result<HashSet<u32>>;
+
+    async fn add_bookmark(&self, item_id: u32, user_id: &str, at: DateTime<Utc>) -> Result<()>;
+
+    async fn remove_bookmark(&self, item_id: u32, user_id: &str) -> Result<()>;
+
+    async fn record_title_change(&self, change: &TitleChange) -> Result<()>;
+
+    /// Every change to a story's title or URL, oldest first.
+    async fn title_changes(&self, item_id: u32) -> Result<Vec<TitleChange>>;
+
+    /// The latest title or URL changes to any story, newest first.
+    async fn recent_title_changes(&self, limit: u32) -> Result<Vec<TitleChange>>;
+
+    /// Move an item that can't be read back out of the item table, keeping
+    /// its record and `reason` for a look later.
+    async fn quarantine_item(&self, item: &Item, reason: &str, at: DateTime<Utc>) -> Result<()>;
+
+    /// The latest quarantined items, newest first.
+    async fn quarantined_items(&self, limit: u32) -> Result<Vec<QuarantinedItem>>;
+
+    async fn get_config(&self, key: &str) -> Result<Option<String>>;
+
+    async fn set_config(&self, key: &str, value: &str) -> Result<()>;
+
+    /// Record a job run. A run without an error also counts as its last success.
+    async fn record_job_run(
+        &self,
+        name: &str,
+        at: DateTime<Utc>,
+        duration_ms: i64,
+        error: Option<&str>,
+    ) -> Result<()>;
+
+    /// How each job last went, by name.
+    async fn job_runs(&self) -> Result<Vec<JobRun>>;
+
+    /// When any job last succeeded.
+    async fn last_job_success(&self) -> Result<Option<NaiveDateTime>>;
+
+    /// The underlying SQLite pool, for the tools that only support SQLite so
+    /// far, like exports and vacuuming.
+    fn sqlite(&self) -> Option<&SqlitePool> {
+        None
+    }
+}
+
+/// Connect to the database at `url`, writing items' JSON with `codec`.
+/// Connections are made lazily, so this doesn't fail when the database is
+/// down.
+pub fn connect(url: &str, codec: Codec) -> Result<Arc<dyn Storage>> {
+    match url.split(':').next() {
+        Some("sqlite") => {
+            let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+            let pool = SqlitePoolOptions::new().connect_lazy_with(options);
+            Ok(Arc::new(SqliteStorage::new(pool).with_codec(codec)))
+        }
+        Some("postgres" | "postgresql") => connect_postgres(url, codec),
+        _ => Err(Error::UnsupportedDatabase(
+            "DATABASE_URL must start with sqlite: or postgres:".into(),
+        )),
+    }
+}
+
+#[cfg(feature = "postgres")]
+fn connect_postgres(url: &str, codec: Codec) -> Result<Arc<dyn Storage>> {
+    Ok(Arc::new(
+        crate::db::PostgresStorage::connect(url)?.with_codec(codec),
+    ))
+}
+
+#[cfg(not(feature = "postgres"))]
+fn connect_postgres(_url: &str, _codec: Codec) -> Result<Arc<dyn Storage>> {
+    Err(Error::UnsupportedDatabase(
+        "PostgreSQL support needs building with `--features postgres`".into(),
+    ))
+}
+
+/// Checks every backend is expected to pass, run by each backend's tests.
+#[cfg(test)]
+pub mod conformance {
+    use super::*;
+    use chrono::Duration;
+
+    fn item(id: i64) -> Item {
+        story(id, "Hi")
+    }
+
+    pub fn story(id: i64, title: &str) -> Item {
+        let json = format!(
+            r#"{{"type":"story","id":{},"by":"dan","time":1175714200,"title":"{}","score":3,"descendants":0}}"#,
+            id, title
+        );
+        serde_json::from_str::<crate::domain::Item>(&json)
+            .unwrap()
+            .try_into()
+            .unwrap()
+    }
+
+    pub async fn stores_items(storage: &dyn Storage) {
+        storage
+            .insert_items(&[item(1), item(2), item(3)], Some(("import_ptr:test", "3")))
+            .await
+            .unwrap();
+        storage.insert_items(&[item(2)], None).await.unwrap();
+
+        assert_eq!(storage.load_item(2).await.unwrap(), Some(item(2)));
+        assert_eq!(storage.load_item(4).await.unwrap(), None);
+
+        let mut got = storage
+            .load_items(&[1, 3, 4])
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|item| item.id())
+            .collect::<Vec<_>>();
+        got.sort_unstable();
+        assert_eq!(got, vec![1, 3]);
+
+        let page = storage.items_after(1, 1).await.unwrap();
+        assert_eq!(page, vec![item(2)]);
+
+        assert_eq!(
+            storage.get_config("import_ptr:test").await.unwrap(),
+            Some("3".into())
+        );
+        let stats = storage.stats().await.unwrap();
+        assert_eq!(stats.item_count, 3);
+        assert_eq!(stats.max_item_id, Some(3));
+    }
+
+    pub async fn searches_titles(storage: &dyn Storage) {
+        storage
+            .insert_items(
+                &[
+                    story(1, "Rust 1.0"),
+                    story(2, "Show HN: rusty"),
+                    story(3, "Go"),
+                    story(4, "100% rust"),
+                ],
+                None,
+            )
+            .await
+            .unwrap();
+
+        let ids = |items: Vec<Item>| items.iter().map(Item::id).collect::<Vec<_>>();
+        let got = storage.search_items("RUST", 10, 0).await.unwrap();
+        assert_eq!(ids(got), vec![4, 2, 1]);
+        let got = storage.search_items("rust", 1, 1).await.unwrap();
+        assert_eq!(ids(got), vec![2]);
+        let got = storage.search_items("0%", 10, 0).await.unwrap();
+        assert_eq!(ids(got), vec![4]);
+        assert!(storage.search_items("_o", 10, 0).await.unwrap().is_empty());
+    }
+
+    pub async fn recodes_items(storage: &dyn Storage) {
+        storage
+            .insert_items(&[item(1), item(2)], None)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            storage
+                .recode_items(&storage.items_after(0, 10).await.unwrap(), Codec::Zstd)
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(
+            storage
+                .recode_items(&storage.items_after(0, 10).await.unwrap(), Codec::Zstd)
+                .await
+                .unwrap(),
+            0
+        );
+
+        let stored = storage.load_item(1).await.unwrap().unwrap();
+        assert_eq!(stored.codec().unwrap(), Codec::Zstd);
+        assert_eq!(stored.json().unwrap(), item(1).json().unwrap());
+        assert_eq!(storage.load_items(&[2]).await.unwrap().len(), 1);
+        assert!(storage.item_revisions(1).await.unwrap().is_empty());
+    }
+
+    pub async fn keeps_revisions(storage: &dyn Storage) {
+        storage.insert_items(&[story(1, "Hi")], None).await.unwrap();
+        storage.insert_items(&[story(1, "Hi")], None).await.unwrap();
+        assert!(storage.item_revisions(1).await.unwrap().is_empty());
+
+        storage
+            .insert_items(&[story(1, "Hello"), story(2, "Hi")], None)
+            .await
+            .unwrap();
+        storage
+            .insert_items(&[story(1, "Hey")], None)
+            .await
+            .unwrap();
+
+        let revisions = storage.item_revisions(1).await.unwrap();
+        let originals = revisions
+            .iter()
+            .map(|revision| (revision.revision, revision.original.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            originals,
+            vec![
+                (1, story(1, "Hi").json().unwrap()),
+                (2, story(1, "Hello").json().unwrap()),
+                (3, story(1, "Hey").json().unwrap()),
+            ]
+        );
+        assert_eq!(
+            revisions[0].created_at,
+            DateTime::from_timestamp(1175714200, 0).unwrap().naive_utc()
+        );
+        assert!(storage.item_revisions(2).await.unwrap().is_empty());
+    }
+
+    pub async fn replaces_unreadable_items(storage: &dyn Storage) {
+        let unreadable = Item {
+            original: vec![0xff, 0xfe],
+            ..item(1)
+        };
+        storage.insert_items(&[unreadable], None).await.unwrap();
+        let stored = storage.load_item(1).await.unwrap().unwrap();
+        assert!(stored.json().is_err());
+
+        storage.insert_items(&[item(1)], None).await.unwrap();
+
+        assert_eq!(storage.load_item(1).await.unwrap(), Some(item(1)));
+        let revisions = storage.item_revisions(1).await.unwrap();
+        let originals = revisions
+            .iter()
+            .map(|revision| (revision.revision, revision.original.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(originals, vec![(1, item(1).json().unwrap())]);
+    }
+
+    pub async fn saves_ranks(storage: &dyn Storage) {
+        let t1 = Utc::now() - Duration::days(100);
+        let t2 = t1 + Duration::minutes(1);
+        let t3 = Utc::now();
+
+        assert!(storage.save_rank(&[40, 41], t1).await.unwrap());
+        assert!(!storage.save_rank(&[40, 41], t2).await.unwrap());
+        assert!(storage.save_rank(&[41, 40], t3).await.unwrap());
+
+        assert_eq!(storage.list("top_stories").await.unwrap(), vec![41, 40]);
+        let ranks = storage
+            .item_metrics(40)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|metric| metric.value)
+            .collect::<Vec<_>>();
+        assert_eq!(ranks, vec![2, 1]);
+
+        storage
+            .delete_metrics_before(t3 - Duration::days(90))
+            .await
+            .unwrap();
+        assert_eq!(storage.item_metrics(40).await.unwrap().len(), 1);
+    }
+
+    pub async fn keeps_bookmarks(storage: &dyn Storage) {
+        let now = Utc::now();
+        storage.add_bookmark(1, "dan", now).await.unwrap();
+        storage
+            .add_bookmark(2, "dan", now + Duration::seconds(1))
+            .await
+            .unwrap();
+        storage.remove_bookmark(1, "dan").await.unwrap();
+        storage
+            .add_bookmark(3, "dan", now + Duration::seconds(2))
+            .await
+            .unwrap();
+
+        assert_eq!(storage.bookmarked_ids().await.unwrap(), vec![3, 2]);
+        assert_eq!(
+            storage.user_bookmarked_ids("dan").await.unwrap(),
+            vec![3, 2]
+        );
+        assert!(storage.user_bookmarked_ids("bob").await.unwrap().is_empty());
+        assert_eq!(
+            storage.bookmarked_among(&[1, 2, 4]).await.unwrap(),
+            HashSet::from([2])
+        );
+    }
+
+    pub async fn records_title_changes(storage: &dyn Storage) {
+        // Whole seconds, as backends differ in how precisely they store times
+        let now = DateTime::from_timestamp(Utc::now().timestamp(), 0)
+            .unwrap()
+            .naive_utc();
+        let change = |item_id, new_title: &str, created_at| TitleChange {
+            item_id,
+            old_title: "Hi".into(),
+            new_title: new_title.into(),
+            old_url: None,
+            new_url: Some("https://dan.com".into()),
+            created_at,
+        };
+        let first = change(1, "Hello", now - Duration::seconds(2));
+        let second = change(1, "Hey", now);
+        let other = change(2, "Yo", now - Duration::seconds(1));
+
+        for change in [&second, &first, &other] {
+            storage.record_title_change(change).await.unwrap();
+        }
+
+        assert_eq!(
+            storage.title_changes(1).await.unwrap(),
+            vec![first, second.clone()]
+        );
+        assert_eq!(
+            storage.recent_title_changes(2).await.unwrap(),
+            vec![second, other]
+        );
+    }
+
+    pub async fn quarantines_items(storage: &dyn Storage) {
+        let now = Utc::now();
+        storage
+            .insert_items(&[item(1), item(2)], None)
+            .await
+            .unwrap();
+
+        storage
+            .quarantine_item(&item(1), "bad json", now - Duration::seconds(1))
+            .await
+            .unwrap();
+        storage
+            .quarantine_item(&item(2), "unknown codec", now)
+            .await
+            .unwrap();
+
+        assert_eq!(storage.load_item(1).await.unwrap(), None);
+        assert!(storage.load_items(&[1, 2]).await.unwrap().is_empty());
+
+        let got = storage.quarantined_items(10).await.unwrap();
+        let got = got
+            .iter()
+            .map(|item| (item.item_id, item.reason.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(got, vec![(2, "unknown codec"), (1, "bad json")]);
+
+        let quarantined = &storage.quarantined_items(1).await.unwrap()[0];
+        assert_eq!(quarantined.original.as_deref(), Some(&item(2).original[..]));
+    }
+
+    pub async fn records_job_runs(storage: &dyn Storage) {
+        let t1 = Utc::now();
+        let t2 = t1 + Duration::seconds(1);
+
+        storage.record_job_run("rank", t1, 5, None).await.unwrap();
+        storage
+            .record_job_run("rank", t2, 7, Some("boom"))
+            .await
+            .unwrap();
+
+        let runs = storage.job_runs().await.unwrap();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].last_duration_ms, 7);
+        assert_eq!(runs[0].last_error.as_deref(), Some("boom"));
+        assert!(runs[0].last_success_at.is_some());
+        assert_eq!(
+            storage.last_job_success().await.unwrap(),
+            runs[0].last_success_at
+        );
+    }
+}
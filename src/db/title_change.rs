@@ -0,0 +1,154 @@
+//! Edits to story titles and URLs, kept in the `title_change` table.
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sqlx::sqlite::SqlitePool;
+
+use crate::result::Result;
+
+/// A story's title or URL as stored before and after a refresh.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TitleChange {
+    pub item_id: i64,
+    pub old_title: String,
+    pub new_title: String,
+    pub old_url: Option<String>,
+    pub new_url: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Clone)]
+pub struct TitleChanges {
+    pool: SqlitePool,
+}
+
+impl TitleChanges {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn record(&self, change: &TitleChange) -> Result<()> {
+        let created_at = DateTime::<Utc>::from_naive_utc_and_offset(change.created_at, Utc);
+        sqlx::query!(
+            r#"
+            INSERT INTO title_change (item_id, old_title, new_title, old_url, new_url, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            "#,
+            change.item_id,
+            change.old_title,
+            change.new_title,
+            change.old_url,
+            change.new_url,
+            created_at
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Every change to a story, oldest first.
+    pub async fn for_item(&self, item_id: u32) -> Result<Vec<TitleChange>> {
+        let changes = sqlx::query_as!(
+            TitleChange,
+            r#"
+            SELECT
+                *
+            FROM
+                title_change
+            WHERE
+                item_id = ?1
+            ORDER BY
+                created_at ASC
+            "#,
+            item_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(changes)
+    }
+
+    /// The latest changes to any story, newest first.
+    pub async fn recent(&self, limit: u32) -> Result<Vec<TitleChange>> {
+        let changes = sqlx::query_as!(
+            TitleChange,
+            r#"
+            SELECT
+                *
+            FROM
+                title_change
+            ORDER BY
+                created_at DESC
+            LIMIT ?1
+            "#,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Duration;
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+    use std::str::FromStr;
+
+    async fn setup() -> TitleChanges {
+        let options = SqliteConnectOptions::from_str("sqlite::memory:").unwrap();
+        let pool = SqlitePoolOptions::new().connect_lazy_with(options);
+        sqlx::migrate!().run(&pool).await.unwrap();
+        TitleChanges::new(pool)
+    }
+
+    fn change(item_id: i64, new_title: &str, created_at: NaiveDateTime) -> TitleChange {
+        TitleChange {
+            item_id,
+            old_title: "Hi".into(),
+            new_title: new_title.into(),
+            old_url: Some("https://dan.com".into()),
+            new_url: None,
+            created_at,
+        }
+    }
+
+    #[tokio::test]
+    async fn loads_an_items_changes_oldest_first() {
+        let changes = setup().await;
+        let now = Utc::now().naive_utc();
+        let first = change(1, "Hello", now - Duration::seconds(1));
+        let second = change(1, "Hey", now);
+
+        changes.record(&second).await.unwrap();
+        changes.record(&first).await.unwrap();
+        changes.record(&change(2, "Yo", now)).await.unwrap();
+
+        assert_eq!(changes.for_item(1).await.unwrap(), vec![first, second]);
+    }
+
+    #[tokio::test]
+    async fn loads_recent_changes_newest_first() {
+        let changes = setup().await;
+        let now = Utc::now().naive_utc();
+
+        for (id, age) in [(1, 3), (2, 1), (3, 2)] {
+            let created_at = now - Duration::seconds(age);
+            changes
+                .record(&change(id, "Hello", created_at))
+                .await
+                .unwrap();
+        }
+
+        let got = changes
+            .recent(2)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|change| change.item_id)
+            .collect::<Vec<_>>();
+        assert_eq!(got, vec![2, 3]);
+    }
+}
@@ -6,15 +6,23 @@ use serde::{Deserialize, Serialize};
 pub struct Comment {
     /// The item's unique id.
     pub id: u32,
-    /// The username of the item's author.
+    /// The username of the item's author. Empty once deleted.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub by: String,
     /// The ids of the item's comments, in ranked display order.
     pub kids: Option<Vec<u32>>,
     /// The comment's parent: either another comment or the relevant story.
     pub parent: u32,
-    /// The comment text. HTML.
+    /// The comment text. HTML. Empty once deleted.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub text: String,
     /// Creation date of the item, in Unix Time.
     #[serde(with = "ts_seconds")]
     pub time: DateTime<Utc>,
+    /// `true` if the item is deleted.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deleted: bool,
+    /// `true` if the item is dead.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dead: bool,
 }
@@ -4,9 +4,12 @@ use serde::{Deserialize, Serialize};
 pub mod comment;
 pub mod job;
 pub mod story;
+pub mod user;
+use chrono::{DateTime, Utc};
 use comment::Comment;
 use job::Job;
 use story::Story;
+pub use user::User;
 
 /// An API item, for example a story or a comment.
 #[derive(Debug, Clone, Deserialize, Serialize, Union)]
@@ -22,6 +25,22 @@ pub enum Item {
 }
 
 impl Item {
+    pub fn id(&self) -> u32 {
+        match self {
+            Item::Story(story) => story.id,
+            Item::Comment(comment) => comment.id,
+            Item::Job(job) => job.id,
+        }
+    }
+
+    pub fn time(&self) -> DateTime<Utc> {
+        match self {
+            Item::Story(story) => story.time,
+            Item::Comment(comment) => comment.time,
+            Item::Job(job) => job.time,
+        }
+    }
+
     pub fn kids(&self) -> Vec<u32> {
         match self {
             Item::Story(story) => story.kids.clone().unwrap_or_default(),
@@ -44,5 +63,6 @@ pub struct Updates {
     /// A list of recently changed items.
     pub items: Vec<u32>,
     /// A list of recently changed usernames.
+    #[allow(dead_code)]
     pub profiles: Vec<String>,
 }
@@ -7,14 +7,18 @@ pub struct Story {
     /// The item's unique id.
     pub id: u32,
     /// The total comment count.
+    #[serde(default)]
     pub descendants: u32,
-    /// The username of the item's author.
+    /// The username of the item's author. Empty once deleted.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub by: String,
     /// The ids of the item's comments, in ranked display order.
     pub kids: Option<Vec<u32>>,
     /// The story's score.
+    #[serde(default)]
     pub score: u32,
-    /// The title of the story.
+    /// The title of the story. Empty once deleted.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub title: String,
     /// The URL of the story.
     pub url: Option<String>,
@@ -23,4 +27,10 @@ pub struct Story {
     /// Creation date of the item, in Unix Time.
     #[serde(with = "ts_seconds")]
     pub time: DateTime<Utc>,
+    /// `true` if the item is deleted.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deleted: bool,
+    /// `true` if the item is dead.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dead: bool,
 }
@@ -0,0 +1,20 @@
+use chrono::{serde::ts_seconds, DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// A user.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct User {
+    /// The user's unique username. Case-sensitive.
+    pub id: String,
+    /// Creation date of the user, in Unix Time.
+    #[serde(with = "ts_seconds")]
+    pub created: DateTime<Utc>,
+    /// The user's karma.
+    #[serde(default)]
+    pub karma: i64,
+    /// The user's optional self-description. HTML.
+    pub about: Option<String>,
+    /// The ids of the user's stories, polls and comments, newest first.
+    #[serde(default)]
+    pub submitted: Vec<u32>,
+}
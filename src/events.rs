@@ -0,0 +1,75 @@
+use futures::{Stream, StreamExt};
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::BroadcastStream;
+
+use crate::domain::Item;
+
+/// Something the background work noticed that subscribers may care about.
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// The ordering of a stored list changed.
+    ListChanged { key: String, ids: Vec<u32> },
+    /// A new version of an item was fetched.
+    ItemUpdated(Item),
+    /// A comment we hadn't seen before was posted somewhere under a story.
+    NewComment { story_id: u32, comment: Item },
+}
+
+/// Fans events out from cron to GraphQL subscriptions.
+#[derive(Clone)]
+pub struct Events {
+    sender: broadcast::Sender<Event>,
+}
+
+impl Events {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self { sender }
+    }
+
+    pub fn publish(&self, event: Event) {
+        // Nobody listening is fine
+        let _ = self.sender.send(event);
+    }
+
+    /// Subscribe to events published from now on. Subscribers that fall
+    /// behind skip the events they missed.
+    pub fn subscribe(&self) -> impl Stream<Item = Event> {
+        BroadcastStream::new(self.sender.subscribe()).filter_map(|event| async move { event.ok() })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Event, Events};
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn delivers_events_to_subscribers() {
+        let events = Events::new(16);
+        let mut stream = Box::pin(events.subscribe());
+
+        events.publish(Event::ListChanged {
+            key: "top_stories".into(),
+            ids: vec![40],
+        });
+
+        match stream.next().await {
+            Some(Event::ListChanged { key, ids }) => {
+                assert_eq!(key, "top_stories");
+                assert_eq!(ids, vec![40]);
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn publishes_without_subscribers() {
+        let events = Events::new(16);
+
+        events.publish(Event::ListChanged {
+            key: "top_stories".into(),
+            ids: vec![],
+        });
+    }
+}
@@ -0,0 +1,610 @@
+//! Streaming the item, item_metric and list tables out for analysis, as JSON
+//! Lines, CSV or Parquet. Rows are read a page at a time, so exports don't
+//! hold a whole table in memory.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use async_graphql::{Enum, InputObject};
+use chrono::{DateTime, Utc};
+use clap::{ArgEnum, Args};
+use parquet::basic::Compression;
+use parquet::column::writer::ColumnWriter;
+use parquet::data_type::ByteArray;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use serde::Serialize;
+use sqlx::sqlite::{SqlitePool, SqliteRow};
+use sqlx::FromRow;
+
+use crate::db::Codec;
+
+/// How many rows are read from SQLite at a time. Each page becomes a Parquet
+/// row group.
+const PAGE_SIZE: u32 = 10_000;
+
+/// Tells apart export files created in the same millisecond.
+static EXPORTS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ArgEnum, Enum)]
+pub enum Table {
+    /// The `item` table.
+    Items,
+    /// The `item_metric` table, e.g. rank over time.
+    Metrics,
+    /// The `list` table, e.g. the current front page.
+    Lists,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ArgEnum, Enum)]
+pub enum Format {
+    /// One JSON object per line. Items are written as HN sent them.
+    Jsonl,
+    /// The denormalized columns, with a header row.
+    Csv,
+    Parquet,
+}
+
+impl Format {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Jsonl => "jsonl",
+            Format::Csv => "csv",
+            Format::Parquet => "parquet",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ArgEnum, Enum)]
+#[serde(rename_all = "lowercase")]
+pub enum ItemType {
+    Story,
+    Comment,
+    Job,
+}
+
+/// Which rows to export. Metrics and lists are filtered by the item they're
+/// about, and by when they were recorded.
+#[derive(Debug, Clone, Default, Args, InputObject)]
+pub struct Filter {
+    /// Only these item types [default: all]
+    #[clap(long = "type", arg_enum)]
+    #[graphql(default)]
+    pub types: Vec<ItemType>,
+    /// Only items with at least this id
+    #[clap(long)]
+    pub min_id: Option<i64>,
+    /// Only items with at most this id
+    #[clap(long)]
+    pub max_id: Option<i64>,
+    /// Only rows from this time on, e.g. 2022-01-01T00:00:00Z
+    #[clap(long)]
+    pub since: Option<DateTime<Utc>>,
+    /// Only rows from before this time
+    #[clap(long)]
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// Create a new file in `dir` for exporting `table`, returning its name.
+/// Names are never reused, so an export can't overwrite another.
+pub fn create_file(dir: &Path, table: Table, format: Format) -> io::Result<(String, File)> {
+    std::fs::create_dir_all(dir)?;
+    loop {
+        let name = format!(
+            "{}-{}-{}.{}",
+            format!("{:?}", table).to_lowercase(),
+            Utc::now().format("%Y%m%dT%H%M%S%.3f"),
+            EXPORTS.fetch_add(1, Ordering::Relaxed),
+            format.extension()
+        );
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(dir.join(&name))
+        {
+            Ok(file) => return Ok((name, file)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Write the rows of `table` that match `filter`, returning how many were
+/// written.
+pub async fn export(
+    pool: &SqlitePool,
+    table: Table,
+    format: Format,
+    filter: &Filter,
+    out: impl Write + Send,
+) -> Result<u64> {
+    match table {
+        Table::Items => export_rows::<ItemRow>(pool, format, filter, out).await,
+        Table::Metrics => export_rows::<MetricRow>(pool, format, filter, out).await,
+        Table::Lists => export_rows::<ListRow>(pool, format, filter, out).await,
+    }
+}
+
+async fn export_rows<R: ExportRow>(
+    pool: &SqlitePool,
+    format: Format,
+    filter: &Filter,
+    out: impl Write + Send,
+) -> Result<u64> {
+    let mut sink = Sink::new::<R>(format, out)?;
+    let mut written = 0;
+    let mut after = 0;
+
+    loop {
+        let page = fetch_page::<R>(pool, filter, after).await?;
+        let last = match page.last() {
+            Some(last) => last.row_id(),
+            None => break,
+        };
+
+        sink.write(&page)?;
+        written += page.len() as u64;
+        after = last;
+    }
+
+    sink.finish()?;
+    Ok(written)
+}
+
+/// Every `ExportRow::QUERY` takes the same parameters, in this order.
+async fn fetch_page<R: ExportRow>(
+    pool: &SqlitePool,
+    filter: &Filter,
+    after: i64,
+) -> Result<Vec<R>> {
+    let types = match filter.types.is_empty() {
+        true => None,
+        false => Some(serde_json::to_string(&filter.types)?),
+    };
+
+    let rows = sqlx::query_as::<_, R>(R::QUERY)
+        .bind(after)
+        .bind(filter.min_id)
+        .bind(filter.max_id)
+        .bind(filter.since)
+        .bind(filter.until)
+        .bind(types)
+        .bind(PAGE_SIZE)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows)
+}
+
+/// A table that can be exported.
+trait ExportRow: Serialize + for<'r> FromRow<'r, SqliteRow> + Send + Unpin {
+    /// Selects a page of rows after `?1` (a rowid), filtered by min id `?2`,
+    /// max id `?3`, since `?4`, until `?5` and a JSON array of item types
+    /// `?6`, up to `?7` rows.
+    const QUERY: &'static str;
+    const PARQUET_SCHEMA: &'static str;
+
+    fn row_id(&self) -> i64;
+
+    fn json(&self) -> Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    /// The rows as Parquet columns, in `PARQUET_SCHEMA` order.
+    fn columns(rows: &[Self]) -> Vec<Column>;
+}
+
+#[derive(Debug, Serialize, FromRow)]
+struct ItemRow {
+    id: i64,
+    #[serde(rename = "type")]
+    #[sqlx(rename = "type")]
+    item_type: Option<String>,
+    username: Option<String>,
+    title: Option<String>,
+    url: Option<String>,
+    body: Option<String>,
+    score: Option<i64>,
+    descendants: Option<i64>,
+    time: Option<DateTime<Utc>>,
+    #[serde(skip)]
+    original: Vec<u8>,
+    #[serde(skip)]
+    codec: Option<String>,
+}
+
+impl ExportRow for ItemRow {
+    const QUERY: &'static str = r#"
+        SELECT
+            id, item_type AS type, username, title, url, body,
+            score, descendants, time, original, codec
+        FROM
+            item
+        WHERE
+            id > ?1
+            AND (?2 IS NULL OR id >= ?2)
+            AND (?3 IS NULL OR id <= ?3)
+            AND (?4 IS NULL OR time >= ?4)
+            AND (?5 IS NULL OR time < ?5)
+            AND (?6 IS NULL OR item_type IN (SELECT value FROM json_each(?6)))
+        ORDER BY
+            id
+        LIMIT ?7
+    "#;
+    const PARQUET_SCHEMA: &'static str = "
+        message item {
+            REQUIRED INT64 id;
+            OPTIONAL BYTE_ARRAY type (UTF8);
+            OPTIONAL BYTE_ARRAY username (UTF8);
+            OPTIONAL BYTE_ARRAY title (UTF8);
+            OPTIONAL BYTE_ARRAY url (UTF8);
+            OPTIONAL BYTE_ARRAY body (UTF8);
+            OPTIONAL INT64 score;
+            OPTIONAL INT64 descendants;
+            OPTIONAL INT64 time (TIMESTAMP(MILLIS, true));
+        }
+    ";
+
+    fn row_id(&self) -> i64 {
+        self.id
+    }
+
+    fn json(&self) -> Result<String> {
+        let codec = Codec::from_marker(self.codec.as_deref())?;
+        Ok(codec.decode(&self.original)?)
+    }
+
+    fn columns(rows: &[Self]) -> Vec<Column> {
+        vec![
+            Column::int(rows, |row| Some(row.id)),
+            Column::text(rows, |row| row.item_type.clone()),
+            Column::text(rows, |row| row.username.clone()),
+            Column::text(rows, |row| row.title.clone()),
+            Column::text(rows, |row| row.url.clone()),
+            Column::text(rows, |row| row.body.clone()),
+            Column::int(rows, |row| row.score),
+            Column::int(rows, |row| row.descendants),
+            Column::int(rows, |row| row.time.map(|time| time.timestamp_millis())),
+        ]
+    }
+}
+
+#[derive(Debug, Serialize, FromRow)]
+struct MetricRow {
+    #[serde(skip)]
+    row_id: i64,
+    item_id: i64,
+    metric: String,
+    value: i64,
+    created_at: DateTime<Utc>,
+}
+
+impl ExportRow for MetricRow {
+    const QUERY: &'static str = r#"
+        SELECT
+            rowid AS row_id, item_id, metric, value, created_at
+        FROM
+            item_metric
+        WHERE
+            rowid > ?1
+            AND (?2 IS NULL OR item_id >= ?2)
+            AND (?3 IS NULL OR item_id <= ?3)
+            AND (?4 IS NULL OR created_at >= ?4)
+            AND (?5 IS NULL OR created_at < ?5)
+            AND (?6 IS NULL OR item_id IN (
+                SELECT id FROM item
+                WHERE item_type IN (SELECT value FROM json_each(?6))
+            ))
+        ORDER BY
+            rowid
+        LIMIT ?7
+    "#;
+    const PARQUET_SCHEMA: &'static str = "
+        message item_metric {
+            REQUIRED INT64 item_id;
+            REQUIRED BYTE_ARRAY metric (UTF8);
+            REQUIRED INT64 value;
+            REQUIRED INT64 created_at (TIMESTAMP(MILLIS, true));
+        }
+    ";
+
+    fn row_id(&self) -> i64 {
+        self.row_id
+    }
+
+    fn columns(rows: &[Self]) -> Vec<Column> {
+        vec![
+            Column::int(rows, |row| Some(row.item_id)),
+            Column::text(rows, |row| Some(row.metric.clone())),
+            Column::int(rows, |row| Some(row.value)),
+            Column::int(rows, |row| Some(row.created_at.timestamp_millis())),
+        ]
+    }
+}
+
+#[derive(Debug, Serialize, FromRow)]
+struct ListRow {
+    #[serde(skip)]
+    row_id: i64,
+    key: String,
+    item_id: i64,
+    ordering: i64,
+    created_at: DateTime<Utc>,
+}
+
+impl ExportRow for ListRow {
+    const QUERY: &'static str = r#"
+        SELECT
+            rowid AS row_id, key, item_id, ordering, created_at
+        FROM
+            list
+        WHERE
+            rowid > ?1
+            AND (?2 IS NULL OR item_id >= ?2)
+            AND (?3 IS NULL OR item_id <= ?3)
+            AND (?4 IS NULL OR created_at >= ?4)
+            AND (?5 IS NULL OR created_at < ?5)
+            AND (?6 IS NULL OR item_id IN (
+                SELECT id FROM item
+                WHERE item_type IN (SELECT value FROM json_each(?6))
+            ))
+        ORDER BY
+            rowid
+        LIMIT ?7
+    "#;
+    const PARQUET_SCHEMA: &'static str = "
+        message list {
+            REQUIRED BYTE_ARRAY key (UTF8);
+            REQUIRED INT64 item_id;
+            REQUIRED INT64 ordering;
+            REQUIRED INT64 created_at (TIMESTAMP(MILLIS, true));
+        }
+    ";
+
+    fn row_id(&self) -> i64 {
+        self.row_id
+    }
+
+    fn columns(rows: &[Self]) -> Vec<Column> {
+        vec![
+            Column::text(rows, |row| Some(row.key.clone())),
+            Column::int(rows, |row| Some(row.item_id)),
+            Column::int(rows, |row| Some(row.ordering)),
+            Column::int(rows, |row| Some(row.created_at.timestamp_millis())),
+        ]
+    }
+}
+
+/// One column of a page, for Parquet.
+enum Column {
+    Int(Vec<Option<i64>>),
+    Text(Vec<Option<String>>),
+}
+
+impl Column {
+    fn int<R>(rows: &[R], value: impl Fn(&R) -> Option<i64>) -> Self {
+        Column::Int(rows.iter().map(value).collect())
+    }
+
+    fn text<R>(rows: &[R], value: impl Fn(&R) -> Option<String>) -> Self {
+        Column::Text(rows.iter().map(value).collect())
+    }
+}
+
+/// Where rows are written, in one of the formats.
+enum Sink<W: Write + Send> {
+    Jsonl(W),
+    Csv(csv::Writer<W>),
+    Parquet(SerializedFileWriter<W>),
+}
+
+impl<W: Write + Send> Sink<W> {
+    fn new<R: ExportRow>(format: Format, out: W) -> Result<Self> {
+        let sink = match format {
+            Format::Jsonl => Sink::Jsonl(out),
+            Format::Csv => Sink::Csv(csv::Writer::from_writer(out)),
+            Format::Parquet => {
+                let schema = Arc::new(parse_message_type(R::PARQUET_SCHEMA)?);
+                let properties = WriterProperties::builder()
+                    .set_compression(Compression::SNAPPY)
+                    .build();
+                Sink::Parquet(SerializedFileWriter::new(
+                    out,
+                    schema,
+                    Arc::new(properties),
+                )?)
+            }
+        };
+
+        Ok(sink)
+    }
+
+    fn write<R: ExportRow>(&mut self, rows: &[R]) -> Result<()> {
+        match self {
+            Sink::Jsonl(out) => {
+                for row in rows {
+                    writeln!(out, "{}", row.json()?)?;
+                }
+            }
+            Sink::Csv(out) => {
+                for row in rows {
+                    out.serialize(row)?;
+                }
+            }
+            Sink::Parquet(out) => {
+                let mut row_group = out.next_row_group()?;
+                for column in R::columns(rows) {
+                    let mut writer = match row_group.next_column()? {
+                        Some(writer) => writer,
+                        None => bail!("more columns than the Parquet schema has"),
+                    };
+                    write_column(writer.untyped(), column)?;
+                    writer.close()?;
+                }
+                row_group.close()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        match self {
+            Sink::Jsonl(mut out) => out.flush()?,
+            Sink::Csv(mut out) => out.flush()?,
+            Sink::Parquet(out) => {
+                out.close()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Write a column, with nulls as missing definition levels.
+fn write_column(writer: &mut ColumnWriter<'_>, column: Column) -> Result<()> {
+    match (writer, column) {
+        (ColumnWriter::Int64ColumnWriter(writer), Column::Int(values)) => {
+            let levels = values
+                .iter()
+                .map(|v| v.is_some() as i16)
+                .collect::<Vec<_>>();
+            let values = values.into_iter().flatten().collect::<Vec<_>>();
+            writer.write_batch(&values, Some(&levels), None)?;
+        }
+        (ColumnWriter::ByteArrayColumnWriter(writer), Column::Text(values)) => {
+            let levels = values
+                .iter()
+                .map(|v| v.is_some() as i16)
+                .collect::<Vec<_>>();
+            let values = values
+                .into_iter()
+                .flatten()
+                .map(|value| ByteArray::from(value.into_bytes()))
+                .collect::<Vec<_>>();
+            writer.write_batch(&values, Some(&levels), None)?;
+        }
+        _ => bail!("column doesn't match the Parquet schema"),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::SqliteStorage;
+    use crate::import::{import, Options};
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+    use std::str::FromStr;
+
+    async fn setup() -> SqlitePool {
+        let options = SqliteConnectOptions::from_str("sqlite::memory:").unwrap();
+        let pool = SqlitePoolOptions::new().connect_lazy_with(options);
+        sqlx::migrate!().run(&pool).await.unwrap();
+
+        let items = r#"{"type":"story","id":1,"by":"dan","time":1175714200,"title":"Hi","score":3,"descendants":1,"kids":[2]}
+{"type":"comment","id":2,"by":"pg","time":1175714300,"text":"Hello","parent":1}
+{"type":"story","id":3,"by":"dan","time":1175714400,"title":"Again","score":1,"descendants":0}
+"#;
+        import(
+            &SqliteStorage::new(pool.clone()),
+            items.as_bytes(),
+            Options::default(),
+        )
+        .await
+        .unwrap();
+        pool
+    }
+
+    async fn export_string(
+        pool: &SqlitePool,
+        table: Table,
+        format: Format,
+        filter: Filter,
+    ) -> String {
+        let mut out = vec![];
+        export(pool, table, format, &filter, &mut out)
+            .await
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[tokio::test]
+    async fn filters_items_by_type_and_id() {
+        let pool = setup().await;
+        let filter = Filter {
+            types: vec![ItemType::Story],
+            min_id: Some(2),
+            ..Default::default()
+        };
+
+        let got = export_string(&pool, Table::Items, Format::Jsonl, filter).await;
+
+        assert_eq!(got.lines().count(), 1);
+        assert!(got.contains(r#""id":3"#));
+    }
+
+    #[tokio::test]
+    async fn writes_csv_columns() {
+        let pool = setup().await;
+        let filter = Filter {
+            until: Some("2007-04-04T19:19:00Z".parse().unwrap()),
+            ..Default::default()
+        };
+
+        let got = export_string(&pool, Table::Items, Format::Csv, filter).await;
+
+        let mut lines = got.lines();
+        assert_eq!(
+            lines.next(),
+            Some("id,type,username,title,url,body,score,descendants,time")
+        );
+        assert_eq!(lines.count(), 2);
+    }
+
+    #[tokio::test]
+    async fn writes_parquet() {
+        let pool = setup().await;
+        let path = std::env::temp_dir().join("twhn-export-test.parquet");
+
+        let file = std::fs::File::create(&path).unwrap();
+        let written = export(
+            &pool,
+            Table::Items,
+            Format::Parquet,
+            &Filter::default(),
+            file,
+        )
+        .await
+        .unwrap();
+
+        let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        let metadata = reader.metadata().file_metadata();
+        assert_eq!(written, 3);
+        assert_eq!(metadata.num_rows(), 3);
+        assert_eq!(metadata.schema_descr().num_columns(), 9);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn never_reuses_file_names() {
+        let dir = std::env::temp_dir().join("twhn-export-names-test");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let (first, mut file) = create_file(&dir, Table::Items, Format::Jsonl).unwrap();
+        file.write_all(b"{}\n").unwrap();
+        let (second, _) = create_file(&dir, Table::Items, Format::Jsonl).unwrap();
+
+        assert_ne!(first, second);
+        assert!(first.starts_with("items-") && first.ends_with(".jsonl"));
+        assert_eq!(std::fs::read(dir.join(&first)).unwrap(), b"{}\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
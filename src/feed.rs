@@ -0,0 +1,628 @@
+//! RSS 2.0 and Atom feeds of HN's lists, saved searches, a user's
+//! submissions and a user's bookmarks, for following HN in a feed reader
+//! with our filters applied:
+//!
+//! - `/feeds/{top|new|best|ask|show|job}.{rss|atom}`
+//! - `/feeds/searches/{name}.{rss|atom}`, for searches in `feeds.searches`
+//! - `/feeds/users/{name}.{rss|atom}`
+//! - `/feeds/users/{name}/bookmarks.{rss|atom}`
+//!
+//! `min_score` and `min_comments` come from the query, the saved search or
+//! `[feeds]`, in that order.
+
+use std::sync::Arc;
+
+use ammonia::clean;
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use serde::Deserialize;
+use warp::path::FullPath;
+use warp::{Filter, Rejection, Reply};
+
+use crate::config::{Config, FeedsConfig};
+use crate::db::Storage;
+use crate::domain::Item;
+use crate::rest;
+use crate::result::{Error, Result};
+use crate::store::Store;
+
+static HN_URL: &str = "https://news.ycombinator.com";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Rss,
+    Atom,
+}
+
+impl Format {
+    fn content_type(self) -> &'static str {
+        match self {
+            Format::Rss => "application/rss+xml; charset=utf-8",
+            Format::Atom => "application/atom+xml; charset=utf-8",
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Feeds {
+    store: Store,
+    storage: Arc<dyn Storage>,
+    config: FeedsConfig,
+    /// How many of a list's items are considered, before filtering.
+    max_items: u32,
+}
+
+/// Filters asked for in the query.
+#[derive(Debug, Default, Deserialize)]
+struct Filters {
+    min_score: Option<u32>,
+    min_comments: Option<u32>,
+}
+
+/// What a feed holds, before filtering.
+struct Feed {
+    title: String,
+    /// Where the same items are on HN.
+    link: String,
+    format: Format,
+    /// A saved search's own filters.
+    filters: Filters,
+    items: Vec<Item>,
+}
+
+/// An item as a feed entry.
+#[derive(Debug, PartialEq)]
+struct Entry {
+    title: String,
+    /// Empty if the item has no author.
+    author: String,
+    /// The story's URL, or else the discussion.
+    link: String,
+    discussion: String,
+    time: DateTime<Utc>,
+    /// HTML, cleaned as `safeText` is.
+    content: String,
+}
+
+/// The `/feeds` routes, each request first passing `limit`. Errors are JSON,
+/// as for `/api`.
+pub fn routes(
+    limit: impl Filter<Extract = (), Error = Rejection> + Clone + Send + Sync + 'static,
+    store: Store,
+    storage: Arc<dyn Storage>,
+    config: &Config,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let feeds = Feeds {
+        store,
+        storage,
+        config: config.feeds.clone(),
+        max_items: config.max_list_items,
+    };
+    let feeds = warp::any().map(move || feeds.clone());
+
+    let list = warp::path!(String)
+        .and(feeds.clone())
+        .then(|file: String, feeds: Feeds| async move { feeds.list(&file).await });
+    let search = warp::path!("searches" / String)
+        .and(feeds.clone())
+        .then(|file: String, feeds: Feeds| async move { feeds.search(&file).await });
+    let user = warp::path!("users" / String)
+        .and(feeds.clone())
+        .then(|file: String, feeds: Feeds| async move { feeds.user(&file).await });
+    let bookmarks = warp::path!("users" / String / String)
+        .and(feeds.clone())
+        .then(|name: String, file: String, feeds: Feeds| async move {
+            feeds.bookmarks(&name, &file).await
+        });
+
+    let endpoints = warp::get()
+        .and(limit)
+        .and(
+            list.or(search)
+                .unify()
+                .or(user)
+                .unify()
+                .or(bookmarks)
+                .unify(),
+        )
+        .and(warp::query::<Filters>())
+        .and(self_url(config.trust_forwarded_for))
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and(feeds)
+        .map(
+            |feed: Result<Feed>,
+             filters: Filters,
+             self_url: String,
+             if_none_match: Option<String>,
+             feeds: Feeds| {
+                let feed = match feed {
+                    Ok(feed) => feed,
+                    Err(err) => return rest::error(&err),
+                };
+                let filters = Filters {
+                    min_score: filters.min_score.or(feed.filters.min_score),
+                    min_comments: filters.min_comments.or(feed.filters.min_comments),
+                };
+                let entries = feeds.entries(&feed.items, &filters);
+                let updated = entries.iter().map(|entry| entry.time).max();
+                let body = match feed.format {
+                    Format::Rss => rss(&feed, &self_url, &entries),
+                    Format::Atom => atom(&feed, &self_url, &entries),
+                };
+
+                rest::validated(
+                    body,
+                    feed.format.content_type(),
+                    updated,
+                    if_none_match.as_deref(),
+                )
+            },
+        );
+
+    warp::path("feeds").and(endpoints.recover(rest::recover))
+}
+
+impl Feeds {
+    async fn list(&self, file: &str) -> Result<Feed> {
+        let (name, format) = split(file)?;
+        let ids = self.store.get_list(name).await?;
+        let link = match name {
+            "top" => format!("{}/news", HN_URL),
+            "new" => format!("{}/newest", HN_URL),
+            "job" => format!("{}/jobs", HN_URL),
+            _ => format!("{}/{}", HN_URL, name),
+        };
+
+        Ok(Feed {
+            title: format!("Hacker News: {}", name),
+            link,
+            format,
+            filters: Filters::default(),
+            items: self.load(ids).await?,
+        })
+    }
+
+    async fn search(&self, file: &str) -> Result<Feed> {
+        let (name, format) = split(file)?;
+        let search = self
+            .config
+            .searches
+            .get(name)
+            .ok_or_else(|| Error::NotFound(format!("no saved search {:?}", name)))?;
+        let items = self.store.search(&search.query, self.max_items, 0).await?;
+
+        Ok(Feed {
+            title: format!("Hacker News: {}", search.query),
+            link: format!("{}/newest", HN_URL),
+            format,
+            filters: Filters {
+                min_score: search.min_score,
+                min_comments: search.min_comments,
+            },
+            items,
+        })
+    }
+
+    async fn user(&self, file: &str) -> Result<Feed> {
+        let (name, format) = split(file)?;
+        let user = self
+            .store
+            .get_user(name)
+            .await?
+            .ok_or_else(|| Error::NotFound(format!("no user {:?}", name)))?;
+
+        Ok(Feed {
+            title: format!("Hacker News: {}'s submissions", user.id),
+            link: format!("{}/submitted?id={}", HN_URL, user.id),
+            format,
+            filters: Filters::default(),
+            items: self.load(user.submitted).await?,
+        })
+    }
+
+    async fn bookmarks(&self, name: &str, file: &str) -> Result<Feed> {
+        let format = match split(file)? {
+            ("bookmarks", format) => format,
+            _ => return Err(Error::NotFound(format!("no feed {:?}", file))),
+        };
+        let ids = self.storage.user_bookmarked_ids(name).await?;
+
+        Ok(Feed {
+            title: format!("Hacker News: {}'s bookmarks", name),
+            link: HN_URL.to_string(),
+            format,
+            filters: Filters::default(),
+            items: self.load(ids).await?,
+        })
+    }
+
+    /// The first `max_items` of `ids`, in order.
+    async fn load(&self, ids: Vec<u32>) -> Result<Vec<Item>> {
+        let ids = ids
+            .into_iter()
+            .take(self.max_items as usize)
+            .collect::<Vec<_>>();
+        let mut items = self.store.get_items(ids.clone()).await?;
+
+        Ok(ids.into_iter().filter_map(|id| items.remove(&id)).collect())
+    }
+
+    /// The entries for `items` that pass `filters`, or else the configured
+    /// ones.
+    fn entries(&self, items: &[Item], filters: &Filters) -> Vec<Entry> {
+        let min_score = filters.min_score.unwrap_or(self.config.min_score);
+        let min_comments = filters.min_comments.unwrap_or(self.config.min_comments);
+
+        items
+            .iter()
+            .filter(|item| {
+                let (score, comments) = match item {
+                    Item::Story(story) => (story.score, story.descendants),
+                    Item::Job(job) => (job.score, 0),
+                    Item::Comment(_) => (0, 0),
+                };
+                score >= min_score && comments >= min_comments
+            })
+            .filter_map(entry)
+            .collect()
+    }
+}
+
+/// A feed's name and format, from a file name like `top.rss`.
+fn split(file: &str) -> Result<(&str, Format)> {
+    match file.rsplit_once('.') {
+        Some((name, "rss")) => Ok((name, Format::Rss)),
+        Some((name, "atom")) => Ok((name, Format::Atom)),
+        _ => Err(Error::NotFound(format!(
+            "no feed {:?}, expected a name ending in .rss or .atom",
+            file
+        ))),
+    }
+}
+
+/// The URL the feed was requested at, for its self link and Atom id. Behind
+/// a proxy trusted with `trust_forwarded_for`, its `X-Forwarded-Proto` says
+/// whether that was HTTPS.
+fn self_url(
+    trust_forwarded_for: bool,
+) -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
+    let query = warp::query::raw().or(warp::any().map(String::new)).unify();
+
+    warp::header::optional::<String>("host")
+        .and(warp::header::optional::<String>("x-forwarded-proto"))
+        .and(warp::path::full())
+        .and(query)
+        .map(
+            move |host: Option<String>, proto: Option<String>, path: FullPath, query: String| {
+                let scheme = match proto {
+                    Some(proto) if trust_forwarded_for && proto == "https" => "https",
+                    _ => "http",
+                };
+                let host = host.unwrap_or_else(|| "localhost".into());
+                let query = if query.is_empty() {
+                    query
+                } else {
+                    format!("?{}", query)
+                };
+                format!("{}://{}{}{}", scheme, host, path.as_str(), query)
+            },
+        )
+}
+
+/// An item as an entry, unless it's deleted or dead.
+fn entry(item: &Item) -> Option<Entry> {
+    let discussion = format!("{}/item?id={}", HN_URL, item.id());
+    let link = |url: &Option<String>| url.clone().unwrap_or_else(|| discussion.clone());
+    let linked = |url: &Option<String>| match url {
+        Some(url) => format!("<p><a href=\"{0}\">{0}</a></p>", escape(url)),
+        None => String::new(),
+    };
+
+    let entry = match item {
+        Item::Story(story) if story.deleted || story.dead => return None,
+        Item::Comment(comment) if comment.deleted || comment.dead => return None,
+        Item::Story(story) => Entry {
+            title: story.title.clone(),
+            author: story.by.clone(),
+            link: link(&story.url),
+            content: format!(
+                "{}{}<p><a href=\"{}\">{} points by {}, {} comments</a></p>",
+                clean(story.text.as_deref().unwrap_or_default()),
+                linked(&story.url),
+                discussion,
+                story.score,
+                escape(&story.by),
+                story.descendants
+            ),
+            discussion,
+            time: story.time,
+        },
+        Item::Comment(comment) => Entry {
+            title: format!("Comment by {}", comment.by),
+            author: comment.by.clone(),
+            link: discussion.clone(),
+            content: format!(
+                "{}<p><a href=\"{}\">On HN</a></p>",
+                clean(&comment.text),
+                discussion
+            ),
+            discussion,
+            time: comment.time,
+        },
+        Item::Job(job) => Entry {
+            title: job.title.clone(),
+            author: String::new(),
+            link: link(&job.url),
+            content: format!(
+                "{}{}<p><a href=\"{}\">On HN</a></p>",
+                clean(job.text.as_deref().unwrap_or_default()),
+                linked(&job.url),
+                discussion
+            ),
+            discussion,
+            time: job.time,
+        },
+    };
+    Some(entry)
+}
+
+fn rss(feed: &Feed, self_url: &str, entries: &[Entry]) -> String {
+    let mut xml = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom" xmlns:dc="http://purl.org/dc/elements/1.1/">
+<channel>
+"#,
+    );
+    xml += &format!(
+        "<title>{}</title>\n<link>{}</link>\n<description>{}</description>\n",
+        escape(&feed.title),
+        escape(&feed.link),
+        escape(&feed.title)
+    );
+    xml += &format!(
+        "<atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>\n",
+        escape(self_url)
+    );
+    if let Some(updated) = entries.iter().map(|entry| entry.time).max() {
+        xml += &format!("<lastBuildDate>{}</lastBuildDate>\n", updated.to_rfc2822());
+    }
+
+    for entry in entries {
+        xml += "<item>\n";
+        xml += &format!("<title>{}</title>\n", escape(&entry.title));
+        xml += &format!("<link>{}</link>\n", escape(&entry.link));
+        xml += &format!("<comments>{}</comments>\n", escape(&entry.discussion));
+        xml += &format!(
+            "<guid isPermaLink=\"true\">{}</guid>\n",
+            escape(&entry.discussion)
+        );
+        if !entry.author.is_empty() {
+            xml += &format!("<dc:creator>{}</dc:creator>\n", escape(&entry.author));
+        }
+        xml += &format!("<pubDate>{}</pubDate>\n", entry.time.to_rfc2822());
+        xml += &format!("<description>{}</description>\n", escape(&entry.content));
+        xml += "</item>\n";
+    }
+
+    xml += "</channel>\n</rss>\n";
+    xml
+}
+
+fn atom(feed: &Feed, self_url: &str, entries: &[Entry]) -> String {
+    // The newest entry rather than now, so an unchanged feed keeps its ETag
+    let updated = entries
+        .iter()
+        .map(|entry| entry.time)
+        .max()
+        .unwrap_or_else(|| Utc.timestamp_opt(0, 0).unwrap());
+
+    let mut xml = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+"#,
+    );
+    xml += &format!("<id>{}</id>\n", escape(self_url));
+    xml += &format!("<title>{}</title>\n", escape(&feed.title));
+    xml += &format!("<updated>{}</updated>\n", timestamp(updated));
+    xml += &format!("<link rel=\"self\" href=\"{}\"/>\n", escape(self_url));
+    xml += &format!(
+        "<link rel=\"alternate\" href=\"{}\"/>\n",
+        escape(&feed.link)
+    );
+    xml += "<author><name>Hacker News</name></author>\n";
+
+    for entry in entries {
+        xml += "<entry>\n";
+        xml += &format!("<id>{}</id>\n", escape(&entry.discussion));
+        xml += &format!("<title>{}</title>\n", escape(&entry.title));
+        xml += &format!("<updated>{}</updated>\n", timestamp(entry.time));
+        if !entry.author.is_empty() {
+            xml += &format!("<author><name>{}</name></author>\n", escape(&entry.author));
+        }
+        xml += &format!(
+            "<link rel=\"alternate\" href=\"{}\"/>\n",
+            escape(&entry.link)
+        );
+        xml += &format!(
+            "<link rel=\"related\" href=\"{}\"/>\n",
+            escape(&entry.discussion)
+        );
+        xml += &format!(
+            "<content type=\"html\">{}</content>\n",
+            escape(&entry.content)
+        );
+        xml += "</entry>\n";
+    }
+
+    xml += "</feed>\n";
+    xml
+}
+
+fn timestamp(at: DateTime<Utc>) -> String {
+    at.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Escape text for XML content and attribute values.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::SavedSearch;
+    use crate::db::SqliteStorage;
+    use crate::hn_client::HnClient;
+    use crate::rate_limit;
+    use serde_json::json;
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    /// Routes over an in-memory database with the top stories ranked and a
+    /// bookmark, and no way to reach HN.
+    async fn setup(
+        feeds: FeedsConfig,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        let options = SqliteConnectOptions::from_str("sqlite::memory:").unwrap();
+        let pool = SqlitePoolOptions::new().connect_lazy_with(options);
+        let storage = SqliteStorage::new(pool);
+        storage.migrate().await.unwrap();
+
+        let items = [
+            json!({"type": "story", "id": 1, "by": "dan", "time": 1_000, "title": "Rust & <you>", "score": 5, "url": "https://rust-lang.org/?a=1&b=2"}),
+            json!({"type": "story", "id": 2, "by": "bob", "time": 2_000, "title": "Ask HN: Rust?", "score": 80, "descendants": 3, "text": "<p>Why?</p><script>x()</script>"}),
+            json!({"type": "story", "id": 3, "time": 3_000, "score": 200, "dead": true}),
+        ]
+        .into_iter()
+        .map(|item| serde_json::from_value::<Item>(item).unwrap().try_into().unwrap())
+        .collect::<Vec<_>>();
+        storage.insert_items(&items, None).await.unwrap();
+        storage.save_rank(&[2, 1, 3], Utc::now()).await.unwrap();
+        storage.add_bookmark(1, "dan", Utc::now()).await.unwrap();
+
+        let client = HnClient::with_base_url("http://127.0.0.1:9", Duration::from_secs(1));
+        let storage: Arc<dyn Storage> = Arc::new(storage);
+        let store = Store::with_client(storage.clone(), client);
+        let config = Config {
+            feeds,
+            ..Default::default()
+        };
+        routes(rate_limit::limit(config.clone()), store, storage, &config)
+    }
+
+    async fn get(
+        routes: &(impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone + 'static),
+        path: &str,
+    ) -> (u16, String) {
+        let response = warp::test::request().path(path).reply(routes).await;
+        let body = String::from_utf8(response.body().to_vec()).unwrap();
+        (response.status().as_u16(), body)
+    }
+
+    fn count(xml: &str, tag: &str) -> usize {
+        xml.matches(tag).count()
+    }
+
+    #[tokio::test]
+    async fn renders_rss() {
+        let routes = setup(FeedsConfig::default()).await;
+
+        let response = warp::test::request()
+            .path("/feeds/top.rss")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.headers()["content-type"],
+            "application/rss+xml; charset=utf-8"
+        );
+        assert!(response.headers().contains_key("etag"));
+
+        let (_, xml) = get(&routes, "/feeds/top.rss").await;
+        assert_eq!(count(&xml, "<item>"), 2, "the dead story is left out");
+        assert!(xml.contains("<title>Rust &amp; &lt;you&gt;</title>"));
+        assert!(xml.contains("<link>https://rust-lang.org/?a=1&amp;b=2</link>"));
+        assert!(xml.contains("<comments>https://news.ycombinator.com/item?id=1</comments>"));
+        assert!(xml.contains("<dc:creator>bob</dc:creator>"));
+        assert!(xml.contains("&lt;p&gt;Why?&lt;/p&gt;"));
+        assert!(!xml.contains("x()"));
+        assert!(xml.find("Ask HN").unwrap() < xml.find("Rust &amp;").unwrap());
+    }
+
+    #[tokio::test]
+    async fn renders_atom() {
+        let routes = setup(FeedsConfig::default()).await;
+
+        let (status, xml) = get(&routes, "/feeds/top.atom?min_score=1").await;
+        assert_eq!(status, 200);
+        assert!(xml.contains("<id>http://localhost/feeds/top.atom?min_score=1</id>"));
+        assert!(xml.contains("<updated>1970-01-01T00:33:20Z</updated>"));
+        assert_eq!(count(&xml, "<entry>"), 2);
+        assert!(
+            xml.contains("<link rel=\"related\" href=\"https://news.ycombinator.com/item?id=2\"/>")
+        );
+
+        let (status, _) = get(&routes, "/feeds/top.json").await;
+        assert_eq!(status, 404);
+        let (status, _) = get(&routes, "/feeds/hot.rss").await;
+        assert_eq!(status, 404);
+    }
+
+    #[tokio::test]
+    async fn filters_by_score_and_comments() {
+        let feeds = FeedsConfig {
+            min_score: 10,
+            searches: [(
+                "rust".to_string(),
+                SavedSearch {
+                    query: "rust".into(),
+                    min_score: Some(0),
+                    min_comments: None,
+                },
+            )]
+            .into(),
+            ..Default::default()
+        };
+        let routes = setup(feeds).await;
+
+        let (_, xml) = get(&routes, "/feeds/top.rss").await;
+        assert_eq!(count(&xml, "<item>"), 1);
+        let (_, xml) = get(&routes, "/feeds/top.rss?min_score=0").await;
+        assert_eq!(count(&xml, "<item>"), 2);
+        let (_, xml) = get(&routes, "/feeds/top.rss?min_score=0&min_comments=1").await;
+        assert_eq!(count(&xml, "<item>"), 1);
+
+        let (_, xml) = get(&routes, "/feeds/searches/rust.rss").await;
+        assert_eq!(count(&xml, "<item>"), 2);
+        let (_, xml) = get(&routes, "/feeds/searches/rust.rss?min_score=50").await;
+        assert_eq!(count(&xml, "<item>"), 1);
+        let (status, _) = get(&routes, "/feeds/searches/go.rss").await;
+        assert_eq!(status, 404);
+    }
+
+    #[tokio::test]
+    async fn follows_bookmarks() {
+        let routes = setup(FeedsConfig::default()).await;
+
+        let (status, xml) = get(&routes, "/feeds/users/dan/bookmarks.atom").await;
+        assert_eq!(status, 200);
+        assert_eq!(count(&xml, "<entry>"), 1);
+        assert!(xml.contains("<title>Rust &amp; &lt;you&gt;</title>"));
+
+        let (_, xml) = get(&routes, "/feeds/users/bob/bookmarks.atom").await;
+        assert_eq!(count(&xml, "<entry>"), 0);
+        let (status, _) = get(&routes, "/feeds/users/dan/likes.atom").await;
+        assert_eq!(status, 404);
+    }
+}
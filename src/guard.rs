@@ -0,0 +1,35 @@
+//! Access control for GraphQL fields.
+
+use async_graphql::{Context, ErrorExtensions, Guard, Result};
+
+use crate::config::Config;
+use crate::result::Error;
+
+/// Added to a request's data when it carries a valid `X-Admin-Token`.
+pub struct Admin;
+
+impl Admin {
+    pub fn authorize(config: &Config, token: Option<&str>) -> Option<Self> {
+        let token = token?;
+        if config.admin_tokens.iter().any(|admin| admin == token) {
+            Some(Admin)
+        } else {
+            None
+        }
+    }
+}
+
+/// Only lets admins through.
+pub struct AdminGuard;
+
+#[async_trait::async_trait]
+impl Guard for AdminGuard {
+    async fn check(&self, ctx: &Context<'_>) -> Result<()> {
+        match ctx.data_opt::<Admin>() {
+            Some(_) => Ok(()),
+            None => {
+                Err(Error::Unauthorized("this needs a valid X-Admin-Token header".into()).extend())
+            }
+        }
+    }
+}
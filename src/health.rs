@@ -0,0 +1,185 @@
+//! Liveness and readiness checks for process supervisors.
+
+use std::collections::BTreeMap;
+
+use chrono::Utc;
+use serde::Serialize;
+use tokio::time::timeout;
+
+use crate::{config::Config, db::Storage, store::Store};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Unavailable,
+    /// Not checked, e.g. turned off in the config.
+    Skipped,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Component {
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl Component {
+    fn ok() -> Self {
+        Self {
+            status: Status::Ok,
+            detail: None,
+        }
+    }
+
+    fn unavailable(detail: impl ToString) -> Self {
+        Self {
+            status: Status::Unavailable,
+            detail: Some(detail.to_string()),
+        }
+    }
+
+    fn skipped(detail: impl ToString) -> Self {
+        Self {
+            status: Status::Skipped,
+            detail: Some(detail.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    pub status: Status,
+    pub components: BTreeMap<&'static str, Component>,
+}
+
+impl Readiness {
+    pub fn is_ready(&self) -> bool {
+        self.status == Status::Ok
+    }
+}
+
+/// Check everything the service needs to do its job. It's ready when no
+/// component is unavailable.
+pub async fn readiness(storage: &dyn Storage, store: &Store, config: &Config) -> Readiness {
+    let mut components = BTreeMap::new();
+
+    components.insert("database", check_database(storage).await);
+    components.insert("migrations", check_migrations(storage).await);
+    components.insert("jobs", check_jobs(storage, config).await);
+    components.insert("upstream", check_upstream(store, config).await);
+
+    let status = if components
+        .values()
+        .any(|component| component.status == Status::Unavailable)
+    {
+        Status::Unavailable
+    } else {
+        Status::Ok
+    };
+
+    Readiness { status, components }
+}
+
+async fn check_database(storage: &dyn Storage) -> Component {
+    match storage.ping().await {
+        Ok(_) => Component::ok(),
+        Err(err) => Component::unavailable(err),
+    }
+}
+
+async fn check_migrations(storage: &dyn Storage) -> Component {
+    match storage.pending_migrations().await {
+        Ok(pending) if pending.is_empty() => Component::ok(),
+        Ok(pending) => Component::unavailable(format!("pending migrations: {:?}", pending)),
+        Err(err) => Component::unavailable(err),
+    }
+}
+
+/// Some job must have succeeded recently, so we're not serving ever staler data.
+async fn check_jobs(storage: &dyn Storage, config: &Config) -> Component {
+    if !config.effective_jobs().iter().any(|(_, job)| job.enabled) {
+        return Component::skipped("no jobs are enabled");
+    }
+
+    let last_success = match storage.last_job_success().await {
+        Ok(last_success) => last_success,
+        Err(err) => return Component::unavailable(err),
+    };
+
+    let max_age = chrono::Duration::seconds(config.ready_max_job_age as i64);
+    match last_success {
+        Some(at) if Utc::now().naive_utc() - at <= max_age => Component::ok(),
+        Some(at) => Component::unavailable(format!("last successful job run was at {}", at)),
+        None => Component::unavailable("no job has succeeded yet"),
+    }
+}
+
+async fn check_upstream(store: &Store, config: &Config) -> Component {
+    if !config.ready_check_upstream {
+        return Component::skipped("turned off in the config");
+    }
+
+    match timeout(config.http_timeout(), store.get_max_item_id()).await {
+        Ok(Ok(_)) => Component::ok(),
+        Ok(Err(err)) => Component::unavailable(err),
+        Err(_) => Component::unavailable("timed out"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::SqliteStorage;
+    use crate::hn_client::HnClient;
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+    use std::str::FromStr;
+    use std::sync::Arc;
+    use tokio::time::Duration;
+
+    async fn setup() -> (Arc<dyn Storage>, Store) {
+        let options = SqliteConnectOptions::from_str("sqlite::memory:").unwrap();
+        let pool = SqlitePoolOptions::new().connect_lazy_with(options);
+        sqlx::migrate!().run(&pool).await.unwrap();
+        let storage: Arc<dyn Storage> = Arc::new(SqliteStorage::new(pool));
+        let client = HnClient::with_base_url("http://127.0.0.1:9", Duration::from_secs(1));
+        let store = Store::with_client(storage.clone(), client);
+        (storage, store)
+    }
+
+    #[tokio::test]
+    async fn ready_after_a_recent_job_run() {
+        let (storage, store) = setup().await;
+        storage
+            .record_job_run("rank", Utc::now(), 1, None)
+            .await
+            .unwrap();
+
+        let got = readiness(&*storage, &store, &Config::default()).await;
+
+        assert!(got.is_ready(), "{:?}", got);
+        assert_eq!(got.components["upstream"].status, Status::Skipped);
+    }
+
+    #[tokio::test]
+    async fn unready_when_jobs_are_stale_or_upstream_is_down() {
+        let (storage, store) = setup().await;
+        let an_hour_ago = Utc::now() - chrono::Duration::hours(1);
+        storage
+            .record_job_run("rank", an_hour_ago, 1, None)
+            .await
+            .unwrap();
+        let config = Config {
+            ready_check_upstream: true,
+            ..Default::default()
+        };
+
+        let got = readiness(&*storage, &store, &config).await;
+
+        assert!(!got.is_ready());
+        assert_eq!(got.components["jobs"].status, Status::Unavailable);
+        assert_eq!(got.components["upstream"].status, Status::Unavailable);
+        assert_eq!(got.components["database"].status, Status::Ok);
+        assert_eq!(got.components["migrations"].status, Status::Ok);
+    }
+}
@@ -1,112 +1,284 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::{domain::Item, domain::Updates, result::Result};
-use reqwest::{self, Client};
+use crate::{
+    domain::Item,
+    domain::Updates,
+    domain::User,
+    metrics,
+    result::{Error, Result},
+    sse::{self, Change, SseParser},
+};
+use futures::{Stream, StreamExt};
+use reqwest::{self, header::ACCEPT, Client, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use tokio::time::{sleep, timeout};
+use tracing::{debug, info, warn};
 
 static API_BASE_URL: &str = "https://hacker-news.firebaseio.com/v0";
 
+/// Firebase sends a keep-alive every 30 seconds, so this much silence means
+/// the connection is dead.
+const STREAM_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
 #[derive(Clone)]
 pub struct HnClient {
     client: Client,
+    stream_client: Client,
+    base_url: String,
 }
 
 impl HnClient {
-    pub fn new() -> Self {
-        let client = reqwest::Client::builder()
-            .timeout(Duration::from_secs(10))
+    pub fn new(timeout: Duration) -> Self {
+        Self::with_base_url(API_BASE_URL, timeout)
+    }
+
+    pub fn with_base_url(base_url: &str, timeout: Duration) -> Self {
+        let client = reqwest::Client::builder().timeout(timeout).build().unwrap();
+        // Streams stay open indefinitely, so only the connect can time out
+        let stream_client = reqwest::Client::builder()
+            .connect_timeout(timeout)
             .build()
             .unwrap();
-        Self { client }
+        let base_url = base_url.trim_end_matches('/').to_string();
+        Self {
+            client,
+            stream_client,
+            base_url,
+        }
     }
 
     pub async fn get_item(&self, id: u32) -> Result<Option<Item>> {
         Ok(self
-            .client
-            .get(&format!("{}/item/{}.json", API_BASE_URL, id))
-            .send()
+            .get(&format!("item/{}.json", id))
             .await?
             .json()
             .await
             .ok())
     }
 
-    pub async fn get_max_item_id(&self) -> Result<u32> {
+    pub async fn get_user(&self, name: &str) -> Result<Option<User>> {
         Ok(self
-            .client
-            .get(&format!("{}/maxitem.json", API_BASE_URL))
-            .send()
+            .get(&format!("user/{}.json", name))
             .await?
             .json()
-            .await?)
+            .await
+            .ok())
+    }
+
+    pub async fn get_max_item_id(&self) -> Result<u32> {
+        Ok(self.get("maxitem.json").await?.json().await?)
     }
 
     pub async fn get_top_stories(&self) -> Result<Vec<u32>> {
-        Ok(self
-            .client
-            .get(&format!("{}/topstories.json", API_BASE_URL))
-            .send()
-            .await?
-            .json()
-            .await?)
+        Ok(self.get("topstories.json").await?.json().await?)
     }
 
     pub async fn get_new_stories(&self) -> Result<Vec<u32>> {
-        Ok(self
-            .client
-            .get(&format!("{}/newstories.json", API_BASE_URL))
-            .send()
-            .await?
-            .json()
-            .await?)
+        Ok(self.get("newstories.json").await?.json().await?)
     }
 
     pub async fn get_best_stories(&self) -> Result<Vec<u32>> {
-        Ok(self
-            .client
-            .get(&format!("{}/beststories.json", API_BASE_URL))
-            .send()
-            .await?
-            .json()
-            .await?)
+        Ok(self.get("beststories.json").await?.json().await?)
     }
 
     pub async fn get_ask_stories(&self) -> Result<Vec<u32>> {
-        Ok(self
-            .client
-            .get(&format!("{}/askstories.json", API_BASE_URL))
-            .send()
-            .await?
-            .json()
-            .await?)
+        Ok(self.get("askstories.json").await?.json().await?)
     }
 
     pub async fn get_show_stories(&self) -> Result<Vec<u32>> {
-        Ok(self
-            .client
-            .get(&format!("{}/showstories.json", API_BASE_URL))
-            .send()
-            .await?
-            .json()
-            .await?)
+        Ok(self.get("showstories.json").await?.json().await?)
     }
 
     pub async fn get_job_stories(&self) -> Result<Vec<u32>> {
-        Ok(self
-            .client
-            .get(&format!("{}/jobstories.json", API_BASE_URL))
-            .send()
-            .await?
-            .json()
-            .await?)
+        Ok(self.get("jobstories.json").await?.json().await?)
     }
 
     pub async fn get_updates(&self) -> Result<Updates> {
-        Ok(self
-            .client
-            .get(&format!("{}/updates.json", API_BASE_URL))
-            .send()
-            .await?
-            .json()
-            .await?)
+        Ok(self.get("updates.json").await?.json().await?)
+    }
+
+    /// GET a path under the API, logging the status and latency. Any status
+    /// but success is an error, so an outage isn't mistaken for a missing
+    /// item.
+    async fn get(&self, path: &str) -> Result<Response> {
+        let url = format!("{}/{}", self.base_url, path);
+        let started = Instant::now();
+        let response = self.client.get(&url).send().await;
+        let elapsed = started.elapsed();
+        let latency_ms = elapsed.as_millis() as u64;
+
+        let endpoint = metrics::endpoint(path);
+        let status = match &response {
+            Ok(response) => {
+                debug!(%url, status = response.status().as_u16(), latency_ms, "GET");
+                response.status().as_str().to_string()
+            }
+            Err(err) => {
+                warn!(%url, latency_ms, error = %err, "GET failed");
+                "error".to_string()
+            }
+        };
+        metrics::UPSTREAM_REQUESTS
+            .with_label_values(&[endpoint, &status])
+            .inc();
+        metrics::UPSTREAM_LATENCY
+            .with_label_values(&[endpoint])
+            .observe(elapsed.as_secs_f64());
+
+        let response = response?;
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            return Err(Error::RateLimited);
+        }
+
+        Ok(response.error_for_status()?)
+    }
+
+    pub fn watch_top_stories(&self) -> impl Stream<Item = Vec<u32>> {
+        self.watch("topstories")
+    }
+
+    pub fn watch_updates(&self) -> impl Stream<Item = Updates> {
+        self.watch("updates")
+    }
+
+    pub fn watch_max_item_id(&self) -> impl Stream<Item = u32> {
+        self.watch("maxitem")
+    }
+
+    /// Follow a path with the Firebase streaming API, yielding the whole value
+    /// every time it changes. Dropped connections are retried with backoff,
+    /// and while streaming is down the path is polled instead.
+    pub fn watch<T: DeserializeOwned>(&self, path: &str) -> impl Stream<Item = T> {
+        let client = self.client.clone();
+        let stream_client = self.stream_client.clone();
+        let url = format!("{}/{}.json", self.base_url, path);
+
+        async_stream::stream! {
+            let mut backoff = MIN_BACKOFF;
+
+            loop {
+                let response = stream_client
+                    .get(&url)
+                    .header(ACCEPT, "text/event-stream")
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status());
+
+                if let Err(err) = &response {
+                    warn!(%url, error = %err, "Couldn't open stream");
+                }
+
+                if let Ok(response) = response {
+                    info!(%url, "Streaming");
+                    let mut body = response.bytes_stream();
+                    let mut parser = SseParser::default();
+                    let mut state = Value::Null;
+
+                    'connection: while let Ok(Some(Ok(chunk))) =
+                        timeout(STREAM_IDLE_TIMEOUT, body.next()).await
+                    {
+                        backoff = MIN_BACKOFF;
+
+                        for event in parser.push(&chunk) {
+                            match sse::apply(&mut state, &event) {
+                                Change::Updated => {
+                                    if let Ok(value) = serde_json::from_value(state.clone()) {
+                                        yield value;
+                                    }
+                                }
+                                Change::Unchanged => {}
+                                Change::Cancelled => break 'connection,
+                            }
+                        }
+                    }
+                }
+
+                // Poll while we wait to reconnect
+                debug!(%url, ?backoff, "Stream ended, reconnecting");
+                let polled = match client.get(&url).send().await {
+                    Ok(response) => response.json::<T>().await.ok(),
+                    Err(_) => None,
+                };
+                if let Some(value) = polled {
+                    yield value;
+                }
+
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Duration, HnClient};
+    use crate::domain::Updates;
+    use futures::{stream, StreamExt};
+    use serde_json::json;
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use warp::{sse::Event, Filter, Reply};
+
+    fn serve<F>(routes: F) -> SocketAddr
+    where
+        F: Filter + Clone + Send + Sync + 'static,
+        F::Extract: Reply,
+    {
+        let (addr, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        addr
+    }
+
+    #[tokio::test]
+    async fn follows_puts_and_patches() {
+        let events = warp::path!("v0" / "updates.json")
+            .and(warp::header::exact("accept", "text/event-stream"))
+            .map(|| {
+                let events = vec![
+                    Event::default().event("put").data(
+                        json!({"path": "/", "data": {"items": [1, 2], "profiles": []}}).to_string(),
+                    ),
+                    Event::default().event("keep-alive").data("null"),
+                    Event::default()
+                        .event("patch")
+                        .data(json!({"path": "/", "data": {"items": [3]}}).to_string()),
+                ];
+                let events = stream::iter(events)
+                    .map(Ok::<_, Infallible>)
+                    .chain(stream::pending());
+                warp::sse::reply(events)
+            });
+        let addr = serve(events);
+        let client =
+            HnClient::with_base_url(&format!("http://{}/v0", addr), Duration::from_secs(10));
+
+        let got = client
+            .watch_updates()
+            .take(2)
+            .map(|updates: Updates| updates.items)
+            .collect::<Vec<_>>()
+            .await;
+
+        let want = vec![vec![1, 2], vec![3]];
+        assert_eq!(got, want);
+    }
+
+    #[tokio::test]
+    async fn polls_when_streaming_is_unavailable() {
+        let events = warp::path!("v0" / "maxitem.json")
+            .and(warp::header::exact("accept", "text/event-stream"))
+            .map(|| warp::reply::with_status("", warp::http::StatusCode::SERVICE_UNAVAILABLE));
+        let poll = warp::path!("v0" / "maxitem.json").map(|| warp::reply::json(&42));
+        let addr = serve(events.or(poll));
+        let client =
+            HnClient::with_base_url(&format!("http://{}/v0", addr), Duration::from_secs(10));
+
+        let got = Box::pin(client.watch_max_item_id()).next().await;
+
+        assert_eq!(got, Some(42));
     }
 }
@@ -0,0 +1,302 @@
+//! Loading items from JSON Lines, such as HN data dumps or the output of
+//! `export`. Each line must be an item in the same shape as the HN API.
+//!
+//! Imports are idempotent: items replace any stored with the same id. Files
+//! also record how far they got, so an interrupted import picks up where it
+//! left off when run again.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use anyhow::{Context, Result};
+use flate2::read::MultiGzDecoder;
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::db::{self, Storage};
+use crate::domain;
+
+/// The first bytes of every gzip file.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+pub struct Options<'a> {
+    /// How many items are written per transaction.
+    pub batch_size: usize,
+    /// Where to record how far the import got, so it can be resumed.
+    pub checkpoint: Option<String>,
+    /// Where rejected lines are written, as JSON with the line number and
+    /// the reason.
+    pub rejects: Option<&'a mut dyn Write>,
+}
+
+impl Default for Options<'_> {
+    fn default() -> Self {
+        Self {
+            batch_size: 10_000,
+            checkpoint: None,
+            rejects: None,
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    pub imported: u64,
+    /// Lines that weren't an item we understand.
+    pub rejected: u64,
+    /// Lines skipped because an earlier run already imported them.
+    pub resumed_after: u64,
+}
+
+/// Open a file, or stdin if there's no path, decompressing it if it's gzipped.
+pub fn open(path: Option<&Path>) -> Result<Box<dyn BufRead + Send>> {
+    let mut input: Box<dyn BufRead + Send> = match path {
+        Some(path) => {
+            let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+            Box::new(BufReader::new(file))
+        }
+        None => Box::new(BufReader::new(io::stdin())),
+    };
+
+    if input.fill_buf()?.starts_with(&GZIP_MAGIC) {
+        input = Box::new(BufReader::new(MultiGzDecoder::new(input)));
+    }
+
+    Ok(input)
+}
+
+/// The checkpoint key for an import from `path`, which is the same however
+/// the path is spelled. It includes the file's size and modification time,
+/// so a different file at the same path starts from the beginning.
+pub fn checkpoint_for(path: &Path) -> String {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+    let (size, modified) = match std::fs::metadata(&path) {
+        Ok(meta) => {
+            let modified = meta
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |modified| modified.as_nanos());
+            (meta.len(), modified)
+        }
+        Err(_) => (0, 0),
+    };
+    format!("import_ptr:{}:{}:{}", size, modified, path.display())
+}
+
+/// Insert every valid item in `input`, replacing any already stored.
+pub async fn import(
+    storage: &dyn Storage,
+    input: impl BufRead,
+    mut options: Options<'_>,
+) -> Result<Summary> {
+    let mut summary = Summary::default();
+    let mut batch = vec![];
+
+    let resume_after = match &options.checkpoint {
+        Some(key) => load_checkpoint(storage, key).await?,
+        None => 0,
+    };
+    if resume_after > 0 {
+        info!(line = resume_after, "Resuming import");
+    }
+
+    let mut number = 0;
+    for line in input.lines() {
+        let line = line?;
+        number += 1;
+        if number <= resume_after {
+            summary.resumed_after += 1;
+            continue;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match serde_json::from_str::<domain::Item>(&line) {
+            Ok(item) => batch.push(db::Item::try_from(item)?),
+            Err(err) => {
+                warn!(line = number, error = %err, "Rejecting invalid item");
+                summary.rejected += 1;
+                if let Some(rejects) = options.rejects.as_mut() {
+                    let reject =
+                        json!({ "line": number, "error": err.to_string(), "record": line });
+                    writeln!(rejects, "{}", reject)?;
+                }
+            }
+        }
+
+        if batch.len() >= options.batch_size {
+            summary.imported += store(storage, &mut batch, &options.checkpoint, number).await?;
+            info!(
+                line = number,
+                imported = summary.imported,
+                rejected = summary.rejected,
+                "Import progress"
+            );
+        }
+    }
+    summary.imported += store(storage, &mut batch, &options.checkpoint, number).await?;
+
+    if let Some(rejects) = options.rejects.as_mut() {
+        rejects.flush()?;
+    }
+
+    Ok(summary)
+}
+
+/// Write a batch and move the checkpoint past it in one transaction, so a
+/// resumed import never skips or repeats work.
+async fn store(
+    storage: &dyn Storage,
+    batch: &mut Vec<db::Item>,
+    checkpoint: &Option<String>,
+    line: u64,
+) -> Result<u64> {
+    let line = line.to_string();
+    let checkpoint = checkpoint.as_deref().map(|key| (key, line.as_str()));
+    storage.insert_items(batch, checkpoint).await?;
+
+    let stored = batch.len() as u64;
+    batch.clear();
+    Ok(stored)
+}
+
+async fn load_checkpoint(storage: &dyn Storage, key: &str) -> Result<u64> {
+    let line = storage
+        .get_config(key)
+        .await?
+        .and_then(|line| line.parse().ok())
+        .unwrap_or(0);
+
+    Ok(line)
+}
+
+/// Forget how far an import got, so the next run starts from the top.
+pub async fn clear_checkpoint(storage: &dyn Storage, key: &str) -> Result<()> {
+    storage.set_config(key, "0").await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::SqliteStorage;
+    use crate::export::{export, Filter, Format, Table};
+    use flate2::{write::GzEncoder, Compression};
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+    use std::str::FromStr;
+
+    static DUMP: &str = r#"{"type":"story","id":1,"by":"dan","time":1175714200,"title":"Hi","score":3,"descendants":1,"kids":[2]}
+not json
+{"type":"comment","id":2,"by":"pg","time":1175714300,"text":"Hello","parent":1}
+{"type":"comment","id":3,"deleted":true,"time":1175714400,"parent":1}
+"#;
+
+    async fn setup() -> SqliteStorage {
+        let options = SqliteConnectOptions::from_str("sqlite::memory:").unwrap();
+        let pool = SqlitePoolOptions::new().connect_lazy_with(options);
+        sqlx::migrate!().run(&pool).await.unwrap();
+        SqliteStorage::new(pool)
+    }
+
+    #[tokio::test]
+    async fn round_trips_through_export() {
+        let storage = setup().await;
+
+        let got = import(&storage, DUMP.as_bytes(), Options::default())
+            .await
+            .unwrap();
+        assert_eq!(got.imported, 3);
+        assert_eq!(got.rejected, 1);
+
+        let mut out = vec![];
+        let written = export(
+            storage.sqlite().unwrap(),
+            Table::Items,
+            Format::Jsonl,
+            &Filter::default(),
+            &mut out,
+        )
+        .await
+        .unwrap();
+        assert_eq!(written, 3);
+
+        let again = setup().await;
+        let got = import(&again, out.as_slice(), Options::default())
+            .await
+            .unwrap();
+        assert_eq!(got.imported, 3);
+    }
+
+    #[tokio::test]
+    async fn reads_gzip_and_reports_rejects() {
+        let storage = setup().await;
+        let path = std::env::temp_dir().join("twhn-import-test.jsonl.gz");
+        let mut gzip = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+        gzip.write_all(DUMP.as_bytes()).unwrap();
+        gzip.finish().unwrap();
+
+        let mut rejects = vec![];
+        let options = Options {
+            rejects: Some(&mut rejects),
+            ..Default::default()
+        };
+        let got = import(&storage, open(Some(&path)).unwrap(), options)
+            .await
+            .unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(got.imported, 3);
+        let rejects = String::from_utf8(rejects).unwrap();
+        let lines = rejects
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["line"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(lines, vec![json!(2)]);
+    }
+
+    #[tokio::test]
+    async fn resumes_from_the_checkpoint() {
+        let storage = setup().await;
+        let options = || Options {
+            batch_size: 1,
+            checkpoint: Some("import_ptr:test".into()),
+            ..Default::default()
+        };
+
+        // Pretend an earlier run stopped after the first two lines
+        let first_two = DUMP.lines().take(2).collect::<Vec<_>>().join("\n");
+        import(&storage, first_two.as_bytes(), options())
+            .await
+            .unwrap();
+
+        let got = import(&storage, DUMP.as_bytes(), options()).await.unwrap();
+
+        assert_eq!(
+            got,
+            Summary {
+                imported: 2,
+                rejected: 0,
+                resumed_after: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn checkpoints_each_version_of_a_file() {
+        let dir = std::env::temp_dir().join("twhn-import-checkpoint-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("items.jsonl");
+        std::fs::write(&path, DUMP).unwrap();
+
+        let key = checkpoint_for(&path);
+        assert_eq!(checkpoint_for(&dir.join(".").join("items.jsonl")), key);
+
+        std::fs::write(&path, DUMP.lines().next().unwrap()).unwrap();
+        assert_ne!(checkpoint_for(&path), key);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
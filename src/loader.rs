@@ -0,0 +1,56 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::{db::Storage, domain::Item, result::Error, store::Store};
+use async_graphql::dataloader::Loader;
+
+/// Batches item lookups made while resolving a single GraphQL query.
+pub struct ItemLoader {
+    store: Store,
+}
+
+impl ItemLoader {
+    pub fn new(store: Store) -> Self {
+        Self { store }
+    }
+}
+
+#[async_trait::async_trait]
+impl Loader<u32> for ItemLoader {
+    type Value = Item;
+    type Error = Arc<Error>;
+
+    async fn load(&self, keys: &[u32]) -> Result<HashMap<u32, Item>, Self::Error> {
+        self.store.get_items(keys.to_vec()).await.map_err(Arc::new)
+    }
+}
+
+/// Batches bookmark status lookups made while resolving a single GraphQL query.
+pub struct BookmarkLoader {
+    storage: Arc<dyn Storage>,
+}
+
+impl BookmarkLoader {
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self { storage }
+    }
+}
+
+#[async_trait::async_trait]
+impl Loader<u32> for BookmarkLoader {
+    type Value = bool;
+    type Error = Arc<Error>;
+
+    async fn load(&self, keys: &[u32]) -> Result<HashMap<u32, bool>, Self::Error> {
+        let bookmarked = self
+            .storage
+            .bookmarked_among(keys)
+            .await
+            .map_err(Arc::new)?;
+
+        Ok(keys
+            .iter()
+            .map(|id| (*id, bookmarked.contains(id)))
+            .collect())
+    }
+}
@@ -0,0 +1,188 @@
+//! Prometheus metrics, served at `/metrics`.
+
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge_vec, Encoder,
+    HistogramVec, IntCounterVec, IntGaugeVec, TextEncoder,
+};
+
+lazy_static! {
+    pub static ref UPSTREAM_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "twhn_upstream_requests_total",
+        "Requests to the HN API by endpoint and status",
+        &["endpoint", "status"]
+    )
+    .unwrap();
+    pub static ref UPSTREAM_LATENCY: HistogramVec = register_histogram_vec!(
+        "twhn_upstream_request_duration_seconds",
+        "Latency of requests to the HN API by endpoint",
+        &["endpoint"]
+    )
+    .unwrap();
+    pub static ref CACHE_LOOKUPS: IntCounterVec = register_int_counter_vec!(
+        "twhn_item_cache_lookups_total",
+        "Item lookups answered from the database (hit) or fetched upstream (miss)",
+        &["result"]
+    )
+    .unwrap();
+    pub static ref QUERY_LATENCY: HistogramVec = register_histogram_vec!(
+        "twhn_db_query_duration_seconds",
+        "Latency of database queries by name",
+        &["query"],
+        vec![0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0]
+    )
+    .unwrap();
+    pub static ref GRAPHQL_OPERATIONS: IntCounterVec = register_int_counter_vec!(
+        "twhn_graphql_operations_total",
+        "GraphQL operations by operation name",
+        &["operation"]
+    )
+    .unwrap();
+    pub static ref RESPONSE_CACHE_LOOKUPS: IntCounterVec = register_int_counter_vec!(
+        "twhn_response_cache_lookups_total",
+        "Anonymous GraphQL queries answered from the response cache (hit) or run (miss)",
+        &["result"]
+    )
+    .unwrap();
+    pub static ref GRAPHQL_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "twhn_graphql_errors_total",
+        "Errors returned from GraphQL operations by operation name",
+        &["operation"]
+    )
+    .unwrap();
+    pub static ref JOB_DURATION: HistogramVec = register_histogram_vec!(
+        "twhn_job_duration_seconds",
+        "How long background job runs take",
+        &["job"],
+        vec![0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0]
+    )
+    .unwrap();
+    pub static ref JOB_FAILURES: IntCounterVec = register_int_counter_vec!(
+        "twhn_job_failures_total",
+        "Background job runs that failed or timed out",
+        &["job"]
+    )
+    .unwrap();
+    pub static ref CURSORS: IntGaugeVec = register_int_gauge_vec!(
+        "twhn_cursor",
+        "Where the ingestion cursors (backfill_ptr, firehose_ptr) are up to",
+        &["name"]
+    )
+    .unwrap();
+    static ref OPERATIONS: OperationLabels = OperationLabels::new(MAX_OPERATIONS);
+}
+
+/// Past this many operation names, the rest are counted as `other`.
+const MAX_OPERATIONS: usize = 100;
+
+/// Longer operation names are counted as `other`.
+const MAX_OPERATION_LEN: usize = 64;
+
+/// The operation names handed out as labels so far, up to `max` of them.
+struct OperationLabels {
+    max: usize,
+    seen: Mutex<HashSet<String>>,
+}
+
+impl OperationLabels {
+    fn new(max: usize) -> Self {
+        Self {
+            max,
+            seen: Mutex::new(HashSet::new()),
+        }
+    }
+
+    fn label(&self, name: Option<&str>, persisted: bool) -> String {
+        let name = match name {
+            Some(name) => name,
+            None => return "anonymous".into(),
+        };
+        let valid = name.len() <= MAX_OPERATION_LEN
+            && name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !persisted || !valid {
+            return "other".into();
+        }
+
+        let mut seen = self.seen.lock().unwrap_or_else(|err| err.into_inner());
+        if seen.contains(name) || seen.len() < self.max {
+            seen.insert(name.to_string());
+            name.to_string()
+        } else {
+            "other".into()
+        }
+    }
+}
+
+/// A low cardinality label for a GraphQL operation: its name when it came as
+/// a persisted query, `anonymous` without a name and `other` for the rest, so
+/// clients can't mint a series per request.
+pub fn operation(name: Option<&str>, persisted: bool) -> String {
+    OPERATIONS.label(name, persisted)
+}
+
+/// Time a database query, recording it under `query`.
+pub async fn time_query<T>(query: &str, future: impl Future<Output = T>) -> T {
+    let started = Instant::now();
+    let output = future.await;
+    QUERY_LATENCY
+        .with_label_values(&[query])
+        .observe(started.elapsed().as_secs_f64());
+    output
+}
+
+/// A low cardinality label for an API path, e.g. `item` for `item/123.json`.
+pub fn endpoint(path: &str) -> &str {
+    let first = path.split('/').next().unwrap_or(path);
+    first.strip_suffix(".json").unwrap_or(first)
+}
+
+/// Everything registered, in the Prometheus text format.
+pub fn render() -> String {
+    let mut buffer = vec![];
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .unwrap_or_default();
+    String::from_utf8(buffer).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn labels_endpoints_without_ids() {
+        assert_eq!(endpoint("item/8863.json"), "item");
+        assert_eq!(endpoint("topstories.json"), "topstories");
+    }
+
+    #[test]
+    fn labels_only_a_few_persisted_operations() {
+        let labels = OperationLabels::new(2);
+
+        assert_eq!(labels.label(None, true), "anonymous");
+        assert_eq!(labels.label(Some("TopItems"), false), "other");
+        assert_eq!(labels.label(Some("TopItems"), true), "TopItems");
+        assert_eq!(labels.label(Some(&"a".repeat(65)), true), "other");
+        assert_eq!(labels.label(Some("top items"), true), "other");
+        assert_eq!(labels.label(Some("Item"), true), "Item");
+        assert_eq!(labels.label(Some("Search"), true), "other");
+        assert_eq!(labels.label(Some("TopItems"), true), "TopItems");
+    }
+
+    #[tokio::test]
+    async fn renders_recorded_metrics() {
+        CACHE_LOOKUPS.with_label_values(&["hit"]).inc();
+        time_query("test_query", async {}).await;
+
+        let got = render();
+
+        assert!(got.contains("twhn_item_cache_lookups_total{result=\"hit\"}"));
+        assert!(got.contains("twhn_db_query_duration_seconds_count{query=\"test_query\"} 1"));
+    }
+}
@@ -0,0 +1,254 @@
+//! Per-client limits on GraphQL, REST and feed requests. Each client gets a
+//! bucket of `rate_limit` requests that refills over a minute, so short
+//! bursts are fine but a steady flood isn't.
+
+use std::collections::{BTreeSet, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use warp::{reject::Reject, Filter, Rejection};
+
+use crate::config::Config;
+use crate::guard::Admin;
+
+/// Past this many clients, the one heard from longest ago is forgotten.
+const MAX_CLIENTS: usize = 10_000;
+
+pub struct RateLimiter {
+    per_minute: u32,
+    max_clients: usize,
+    clients: Mutex<Clients>,
+}
+
+#[derive(Default)]
+struct Clients {
+    buckets: HashMap<String, Bucket>,
+    /// Every client in `buckets` by when it was last updated, oldest first.
+    by_age: BTreeSet<(Instant, String)>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// A request turned away because its client is over the limit.
+#[derive(Debug)]
+pub struct Limited {
+    pub retry_after: Duration,
+}
+
+impl Reject for Limited {}
+
+impl RateLimiter {
+    /// Allow `per_minute` requests per client. 0 allows everything.
+    pub fn new(per_minute: u32) -> Self {
+        Self {
+            per_minute,
+            max_clients: MAX_CLIENTS,
+            clients: Mutex::new(Clients::default()),
+        }
+    }
+
+    /// Take a request from `client`'s allowance, or say how long until one
+    /// is free.
+    pub fn check(&self, client: &str, now: Instant) -> Result<(), Duration> {
+        if self.per_minute == 0 {
+            return Ok(());
+        }
+
+        let capacity = self.per_minute as f64;
+        let per_second = capacity / 60.0;
+        let mut clients = self.clients.lock().unwrap_or_else(|err| err.into_inner());
+
+        let Clients { buckets, by_age } = &mut *clients;
+
+        match buckets.get(client) {
+            Some(bucket) => {
+                by_age.remove(&(bucket.updated, client.to_string()));
+            }
+            None => {
+                while buckets.len() >= self.max_clients {
+                    match by_age.pop_first() {
+                        Some((_, oldest)) => buckets.remove(&oldest),
+                        None => break,
+                    };
+                }
+            }
+        }
+        by_age.insert((now, client.to_string()));
+
+        let bucket = buckets.entry(client.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        bucket.tokens = bucket.refilled(now, capacity, per_second);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_second))
+        }
+    }
+}
+
+impl Bucket {
+    fn refilled(&self, now: Instant, capacity: f64, per_second: f64) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        (self.tokens + elapsed * per_second).min(capacity)
+    }
+}
+
+/// Who a request comes from: an admin by token, anyone else by IP address.
+fn client(
+    config: &Config,
+    remote: Option<SocketAddr>,
+    forwarded_for: Option<&str>,
+    token: Option<&str>,
+) -> String {
+    if let (Some(token), Some(_)) = (token, Admin::authorize(config, token)) {
+        return format!("token:{}", token);
+    }
+
+    let forwarded = forwarded_for
+        .filter(|_| config.trust_forwarded_for)
+        .and_then(|header| header.split(',').next())
+        .and_then(|ip| ip.trim().parse::<IpAddr>().ok());
+    match forwarded.or_else(|| remote.map(|addr| addr.ip())) {
+        Some(ip) => format!("ip:{}", ip),
+        None => "unknown".to_string(),
+    }
+}
+
+/// Passes requests through while their client is under `rate_limit`, and
+/// rejects them with [`Limited`] once it's over.
+pub fn limit(config: Config) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    let limiter = Arc::new(RateLimiter::new(config.rate_limit));
+    warp::addr::remote()
+        .and(warp::header::optional::<String>("x-forwarded-for"))
+        .and(warp::header::optional::<String>("x-admin-token"))
+        .and_then(
+            move |remote: Option<SocketAddr>,
+                  forwarded_for: Option<String>,
+                  token: Option<String>| {
+                let client = client(&config, remote, forwarded_for.as_deref(), token.as_deref());
+                let checked = limiter.check(&client, Instant::now());
+                async move {
+                    checked.map_err(|retry_after| warp::reject::custom(Limited { retry_after }))
+                }
+            },
+        )
+        .untuple_one()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn allows_a_burst_then_refills() {
+        let limiter = RateLimiter::new(60);
+        let start = Instant::now();
+
+        for _ in 0..60 {
+            assert!(limiter.check("dan", start).is_ok());
+        }
+        assert_eq!(limiter.check("dan", start), Err(Duration::from_secs(1)));
+        assert!(limiter.check("bob", start).is_ok());
+
+        let later = start + Duration::from_secs(2);
+        assert!(limiter.check("dan", later).is_ok());
+        assert!(limiter.check("dan", later).is_ok());
+        assert!(limiter.check("dan", later).is_err());
+    }
+
+    #[test]
+    fn forgets_the_longest_idle_client_when_full() {
+        let limiter = RateLimiter {
+            max_clients: 2,
+            ..RateLimiter::new(1)
+        };
+        let start = Instant::now();
+
+        assert!(limiter.check("dan", start).is_ok());
+        assert!(limiter
+            .check("bob", start + Duration::from_millis(1))
+            .is_ok());
+        assert!(limiter
+            .check("dan", start + Duration::from_millis(2))
+            .is_err());
+
+        // bob was heard from before dan last tried, so bob is dropped
+        assert!(limiter
+            .check("eve", start + Duration::from_millis(3))
+            .is_ok());
+        let clients = limiter.clients.lock().unwrap();
+        assert_eq!(clients.buckets.len(), 2);
+        assert_eq!(clients.by_age.len(), 2);
+        assert!(!clients.buckets.contains_key("bob"));
+        drop(clients);
+
+        // dan is still remembered, and still out of requests
+        assert!(limiter
+            .check("dan", start + Duration::from_millis(4))
+            .is_err());
+    }
+
+    #[test]
+    fn zero_means_no_limit() {
+        let limiter = RateLimiter::new(0);
+        let now = Instant::now();
+
+        for _ in 0..1000 {
+            assert!(limiter.check("dan", now).is_ok());
+        }
+    }
+
+    #[test]
+    fn tells_clients_apart_by_token_or_ip() {
+        let token = "0123456789abcdef";
+        let config = Config {
+            admin_tokens: vec![token.into()],
+            ..Default::default()
+        };
+        let remote = Some(SocketAddr::from(([10, 0, 0, 1], 4000)));
+
+        assert_eq!(
+            client(&config, remote, None, Some(token)),
+            "token:0123456789abcdef"
+        );
+        assert_eq!(client(&config, remote, None, Some("guess")), "ip:10.0.0.1");
+        assert_eq!(
+            client(&config, remote, Some("1.2.3.4"), None),
+            "ip:10.0.0.1"
+        );
+
+        let config = Config {
+            trust_forwarded_for: true,
+            ..config
+        };
+        assert_eq!(
+            client(&config, remote, Some("1.2.3.4, 10.0.0.2"), None),
+            "ip:1.2.3.4"
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_requests_over_the_limit() {
+        let config = Config {
+            rate_limit: 2,
+            ..Default::default()
+        };
+        let filter = limit(config).map(|| "ok");
+        let request = || warp::test::request().remote_addr(([10, 0, 0, 1], 4000).into());
+
+        for _ in 0..2 {
+            assert_eq!(request().reply(&filter).await.status(), 200);
+        }
+        let rejected = request().filter(&filter).await.unwrap_err();
+        assert!(rejected.find::<Limited>().is_some());
+    }
+}
@@ -0,0 +1,266 @@
+//! A short-lived cache of responses to anonymous GraphQL queries, keyed by
+//! the hash of the query document, the operation and the variables. The
+//! same few front page queries make up most traffic, and their answers only
+//! change when the lists do, so the cache is cleared whenever a list changes
+//! and after every mutation.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use async_graphql::extensions::apollo_persisted_queries::{CacheStorage, LruCacheStorage};
+use async_graphql::parser::{parse_query, types::OperationType};
+use async_graphql::{Request, Response, Value};
+use futures::{Stream, StreamExt};
+use sha2::{Digest, Sha256};
+
+use crate::events::Event;
+
+/// Past this many responses, expired ones are dropped, and if that doesn't
+/// help new ones aren't kept.
+const MAX_ENTRIES: usize = 1_000;
+
+pub struct ResponseCache {
+    ttl: Duration,
+    /// Where persisted queries are kept, to find the document of a request
+    /// that only sends its hash.
+    persisted: Option<LruCacheStorage>,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+struct Entry {
+    data: Value,
+    expires: Instant,
+}
+
+/// How a request relates to the cache.
+#[derive(Debug, PartialEq)]
+pub enum Caching {
+    /// An anonymous query, whose response is kept under this key.
+    Key(String),
+    /// A mutation, which may change what cached responses say.
+    Invalidates,
+    /// Anything else.
+    Bypass,
+}
+
+impl ResponseCache {
+    /// Keep responses for `ttl`. Zero keeps nothing.
+    pub fn new(ttl: Duration, persisted: Option<LruCacheStorage>) -> Self {
+        Self {
+            ttl,
+            persisted,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn caching(&self, request: &Request, anonymous: bool) -> Caching {
+        let document = match self.document(request).await {
+            Some(document) => document,
+            None => return Caching::Bypass,
+        };
+
+        match operation_type(&document, request.operation_name.as_deref()) {
+            Some(OperationType::Mutation) => Caching::Invalidates,
+            Some(OperationType::Query) if anonymous && !self.ttl.is_zero() => {
+                let variables = serde_json::to_string(&request.variables).unwrap_or_default();
+                Caching::Key(format!(
+                    "{:x}:{}:{}",
+                    Sha256::digest(document.as_bytes()),
+                    request.operation_name.as_deref().unwrap_or_default(),
+                    variables
+                ))
+            }
+            _ => Caching::Bypass,
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<Response> {
+        let entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+        entries
+            .get(key)
+            .filter(|entry| entry.expires > Instant::now())
+            .map(|entry| Response::new(entry.data.clone()))
+    }
+
+    /// The response kept under `key`, and whether there was one, or else
+    /// what `execute` answers, kept from now on. Hits leave the expiry
+    /// alone, so even a query asked constantly is re-run every `ttl`.
+    pub async fn get_or_execute(
+        &self,
+        key: String,
+        execute: impl Future<Output = Response>,
+    ) -> (Response, bool) {
+        if let Some(response) = self.get(&key) {
+            return (response, true);
+        }
+
+        let response = execute.await;
+        self.put(key, &response);
+        (response, false)
+    }
+
+    /// Keep `response` under `key`, unless it has errors.
+    pub fn put(&self, key: String, response: &Response) {
+        if response.is_err() {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+        if entries.len() >= MAX_ENTRIES {
+            entries.retain(|_, entry| entry.expires > now);
+        }
+        if entries.len() < MAX_ENTRIES {
+            let data = response.data.clone();
+            let expires = now + self.ttl;
+            entries.insert(key, Entry { data, expires });
+        }
+    }
+
+    pub fn clear(&self) {
+        self.entries
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clear();
+    }
+
+    /// Clear the cache whenever a list changes, until `events` ends.
+    pub async fn clear_on_list_changes(&self, events: impl Stream<Item = Event>) {
+        futures::pin_mut!(events);
+        while let Some(event) = events.next().await {
+            if let Event::ListChanged { .. } = event {
+                self.clear();
+            }
+        }
+    }
+
+    /// The document a request runs: the one it sent, or the one persisted
+    /// under the hash it sent instead.
+    async fn document(&self, request: &Request) -> Option<String> {
+        if !request.query.is_empty() {
+            return Some(request.query.clone());
+        }
+
+        let hash = match request.extensions.get("persistedQuery") {
+            Some(Value::Object(persisted)) => match persisted.get("sha256Hash") {
+                Some(Value::String(hash)) => hash.clone(),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        self.persisted.as_ref()?.get(hash).await
+    }
+}
+
+/// The type of the operation a request runs, if the document parses.
+fn operation_type(document: &str, operation_name: Option<&str>) -> Option<OperationType> {
+    let document = parse_query(document).ok()?;
+    let (_, operation) = document.operations.iter().find(|(name, _)| {
+        operation_name.is_none() || name.map(|name| name.as_str()) == operation_name
+    })?;
+
+    Some(operation.node.ty)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use async_graphql::value;
+
+    const TOP: &str = "query Top { topItems { id } }";
+
+    fn cache() -> ResponseCache {
+        ResponseCache::new(Duration::from_secs(60), Some(LruCacheStorage::new(16)))
+    }
+
+    #[tokio::test]
+    async fn keys_anonymous_queries_by_document_and_variables() {
+        let cache = cache();
+
+        let plain = cache.caching(&Request::new(TOP), true).await;
+        assert!(matches!(plain, Caching::Key(_)));
+        assert_eq!(cache.caching(&Request::new(TOP), true).await, plain);
+
+        let limited = Request::new(TOP).variables(async_graphql::Variables::from_json(
+            serde_json::json!({ "limit": 5 }),
+        ));
+        assert_ne!(cache.caching(&limited, true).await, plain);
+        assert_eq!(
+            cache.caching(&Request::new(TOP), false).await,
+            Caching::Bypass
+        );
+
+        let bookmark = Request::new("mutation { bookmarkItem(itemId: 1) { id } }");
+        assert_eq!(cache.caching(&bookmark, true).await, Caching::Invalidates);
+        let garbled = Request::new("{ nope");
+        assert_eq!(cache.caching(&garbled, true).await, Caching::Bypass);
+    }
+
+    #[tokio::test]
+    async fn finds_persisted_documents_by_hash() {
+        let cache = cache();
+        let hash = format!("{:x}", Sha256::digest(TOP.as_bytes()));
+        let request = || {
+            let mut request = Request::new("");
+            request.extensions.insert(
+                "persistedQuery".into(),
+                value!({ "version": 1, "sha256Hash": hash.clone() }),
+            );
+            request
+        };
+
+        assert_eq!(cache.caching(&request(), true).await, Caching::Bypass);
+
+        cache
+            .persisted
+            .as_ref()
+            .unwrap()
+            .set(hash.clone(), TOP.into())
+            .await;
+        assert_eq!(
+            cache.caching(&request(), true).await,
+            cache.caching(&Request::new(TOP), true).await
+        );
+    }
+
+    #[tokio::test]
+    async fn keeps_responses_until_they_expire_or_lists_change() {
+        let cache = cache();
+        let response = || Response::new(value!({ "topItems": [] }));
+
+        cache.put("top".into(), &response());
+        assert_eq!(cache.get("top"), Some(response()));
+
+        let events = futures::stream::iter(vec![Event::ListChanged {
+            key: "top_stories".into(),
+            ids: vec![1],
+        }]);
+        cache.clear_on_list_changes(events).await;
+        assert_eq!(cache.get("top"), None);
+
+        let brief = ResponseCache::new(Duration::from_millis(1), None);
+        brief.put("top".into(), &response());
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(brief.get("top"), None);
+    }
+
+    #[tokio::test]
+    async fn hits_do_not_extend_entries() {
+        let cache = ResponseCache::new(Duration::from_millis(100), None);
+        let response = |n: i32| async move { Response::new(value!({ "n": n })) };
+
+        let (first, hit) = cache.get_or_execute("top".into(), response(1)).await;
+        assert!(!hit);
+        std::thread::sleep(Duration::from_millis(60));
+        let (again, hit) = cache.get_or_execute("top".into(), response(2)).await;
+        assert!(hit);
+        assert_eq!(again, first);
+
+        std::thread::sleep(Duration::from_millis(60));
+        let (fresh, hit) = cache.get_or_execute("top".into(), response(3)).await;
+        assert!(!hit);
+        assert_eq!(fresh, Response::new(value!({ "n": 3 })));
+    }
+}
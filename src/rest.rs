@@ -0,0 +1,504 @@
+//! REST/JSON routes under `/api`, for scripts and integrations that can't
+//! speak GraphQL. They answer from the same `Store` calls as the GraphQL
+//! queries, page with `offset` and `limit`, and carry an `ETag` so clients
+//! can revalidate with `If-None-Match` instead of downloading the same
+//! answer again.
+
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use ::http::StatusCode;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use warp::http::Response as HttpResponse;
+use warp::hyper::Body;
+use warp::{Filter, Rejection, Reply};
+
+use crate::config::Config;
+use crate::db::Storage;
+use crate::domain::Item;
+use crate::rate_limit::Limited;
+use crate::result::{Error, Result};
+use crate::store::Store;
+
+type Response = HttpResponse<Body>;
+
+#[derive(Clone)]
+struct Api {
+    store: Store,
+    storage: Arc<dyn Storage>,
+    max_list_items: u32,
+}
+
+/// Which part of a list to return. `limit` defaults to, and is capped at,
+/// `max_list_items`.
+#[derive(Debug, Default, Deserialize)]
+struct Page {
+    offset: Option<u32>,
+    limit: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Search {
+    q: String,
+    offset: Option<u32>,
+    limit: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+struct Paged<T> {
+    items: Vec<T>,
+    offset: u32,
+    limit: u32,
+    /// How many there are in all, where that's known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total: Option<usize>,
+}
+
+/// A JSON answer, and when the newest content in it was posted or edited.
+struct Answer {
+    body: Value,
+    last_modified: Option<DateTime<Utc>>,
+}
+
+/// The `/api` routes, each request first passing `limit`. Anything under
+/// `/api` is answered here, with errors as JSON, rather than falling through
+/// to GraphQL.
+pub fn routes(
+    limit: impl Filter<Extract = (), Error = Rejection> + Clone + Send + Sync + 'static,
+    store: Store,
+    storage: Arc<dyn Storage>,
+    config: &Config,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let api = Api {
+        store,
+        storage,
+        max_list_items: config.max_list_items,
+    };
+    let api = warp::any().map(move || api.clone());
+    let if_none_match = warp::header::optional::<String>("if-none-match");
+
+    let item = warp::path!("items" / u32)
+        .and(api.clone())
+        .then(|id, api: Api| async move { api.item(id).await });
+    let tree = warp::path!("items" / u32 / "tree")
+        .and(warp::query::<Page>())
+        .and(api.clone())
+        .then(|id, page, api: Api| async move { api.tree(id, page).await });
+    let list = warp::path!("lists" / String)
+        .and(warp::query::<Page>())
+        .and(api.clone())
+        .then(|name: String, page, api: Api| async move { api.list(&name, page).await });
+    let search = warp::path!("search")
+        .and(warp::query::<Search>())
+        .and(api.clone())
+        .then(|search, api: Api| async move { api.search(search).await });
+    let user = warp::path!("users" / String)
+        .and(warp::query::<Page>())
+        .and(api)
+        .then(|name: String, page, api: Api| async move { api.user(&name, page).await });
+
+    let endpoints = warp::get()
+        .and(limit)
+        .and(
+            item.or(tree)
+                .unify()
+                .or(list)
+                .unify()
+                .or(search)
+                .unify()
+                .or(user)
+                .unify(),
+        )
+        .and(if_none_match)
+        .map(|answer: Result<Answer>, if_none_match: Option<String>| {
+            respond(answer, if_none_match.as_deref())
+        });
+
+    warp::path("api").and(endpoints.recover(recover))
+}
+
+impl Api {
+    async fn item(&self, id: u32) -> Result<Answer> {
+        let item = self.found(id).await?;
+        let edited = self
+            .storage
+            .item_revisions(id)
+            .await?
+            .last()
+            .map(|revision| DateTime::<Utc>::from_naive_utc_and_offset(revision.created_at, Utc));
+
+        Ok(Answer {
+            last_modified: edited.into_iter().chain([item.time()]).max(),
+            body: json!(item),
+        })
+    }
+
+    /// An item and a page of its descendants, in id order, which is the
+    /// order they were posted in.
+    async fn tree(&self, id: u32, page: Page) -> Result<Answer> {
+        let item = self.found(id).await?;
+        let mut descendants = self
+            .store
+            .get_descendants(id)
+            .await?
+            .into_values()
+            .collect::<Vec<_>>();
+        descendants.sort_unstable_by_key(Item::id);
+
+        let last_modified = descendants.iter().chain([&item]).map(Item::time).max();
+        let descendants = self.page(descendants, page);
+
+        Ok(Answer {
+            body: json!({ "item": item, "descendants": descendants }),
+            last_modified,
+        })
+    }
+
+    async fn list(&self, name: &str, page: Page) -> Result<Answer> {
+        let ids = self.store.get_list(name).await?;
+        self.items(ids, page).await
+    }
+
+    async fn search(&self, search: Search) -> Result<Answer> {
+        if search.q.trim().is_empty() {
+            return Err(Error::InvalidArgument("q must not be empty".into()));
+        }
+
+        let (offset, limit) = self.bounds(search.offset, search.limit);
+        let items = self.store.search(search.q.trim(), limit, offset).await?;
+        let last_modified = items.iter().map(Item::time).max();
+        let found = Paged {
+            items,
+            offset,
+            limit,
+            total: None,
+        };
+
+        Ok(Answer {
+            body: json!(found),
+            last_modified,
+        })
+    }
+
+    /// A user and a page of what they submitted, newest first.
+    async fn user(&self, name: &str, page: Page) -> Result<Answer> {
+        let user = self
+            .store
+            .get_user(name)
+            .await?
+            .ok_or_else(|| Error::NotFound(format!("no user {:?}", name)))?;
+        let submitted = self.items(user.submitted.clone(), page).await?;
+
+        Ok(Answer {
+            last_modified: submitted.last_modified.or(Some(user.created)),
+            body: json!({ "user": user, "submitted": submitted.body }),
+        })
+    }
+
+    async fn found(&self, id: u32) -> Result<Item> {
+        self.store
+            .get_item(id)
+            .await?
+            .ok_or_else(|| Error::NotFound(format!("no item {}", id)))
+    }
+
+    /// A page of `ids`, loaded and kept in order.
+    async fn items(&self, ids: Vec<u32>, page: Page) -> Result<Answer> {
+        let ids = self.page(ids, page);
+        let mut loaded = self.store.get_items(ids.items.clone()).await?;
+        let items = ids
+            .items
+            .iter()
+            .filter_map(|id| loaded.remove(id))
+            .collect::<Vec<_>>();
+        let last_modified = items.iter().map(Item::time).max();
+        let page = Paged {
+            items,
+            offset: ids.offset,
+            limit: ids.limit,
+            total: ids.total,
+        };
+
+        Ok(Answer {
+            body: json!(page),
+            last_modified,
+        })
+    }
+
+    fn page<T>(&self, all: Vec<T>, page: Page) -> Paged<T> {
+        let (offset, limit) = self.bounds(page.offset, page.limit);
+        let total = all.len();
+        let items = all
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect();
+
+        Paged {
+            items,
+            offset,
+            limit,
+            total: Some(total),
+        }
+    }
+
+    fn bounds(&self, offset: Option<u32>, limit: Option<u32>) -> (u32, u32) {
+        let limit = limit.unwrap_or(self.max_list_items);
+        (offset.unwrap_or(0), limit.min(self.max_list_items))
+    }
+}
+
+/// The answer as JSON with its validators, or the error as JSON.
+fn respond(answer: Result<Answer>, if_none_match: Option<&str>) -> Response {
+    match answer {
+        Ok(answer) => validated(
+            answer.body.to_string(),
+            "application/json",
+            answer.last_modified,
+            if_none_match,
+        ),
+        Err(err) => error(&err),
+    }
+}
+
+/// `body` with an `ETag` and `Last-Modified`, or 304 if the client already
+/// has it. Scores and ranks change without the content changing, so
+/// `Last-Modified` is informational and only the `ETag` is compared.
+pub fn validated(
+    body: String,
+    content_type: &str,
+    last_modified: Option<DateTime<Utc>>,
+    if_none_match: Option<&str>,
+) -> Response {
+    let etag = format!("\"{:x}\"", Sha256::digest(body.as_bytes()));
+
+    let mut response = HttpResponse::builder().header("etag", &etag);
+    if let Some(last_modified) = last_modified {
+        response = response.header("last-modified", http_date(last_modified));
+    }
+
+    let response = if if_none_match.is_some_and(|tags| matches(tags, &etag)) {
+        response
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Body::empty())
+    } else {
+        response
+            .header("content-type", content_type)
+            .body(Body::from(body))
+    };
+    response.unwrap_or_else(|err| error(&Error::InvalidArgument(err.to_string())))
+}
+
+/// Whether an `If-None-Match` header names `etag`. Weak tags compare equal
+/// to strong ones, as they should for `GET`.
+fn matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+fn http_date(at: DateTime<Utc>) -> String {
+    at.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// An error as JSON, with the status its code calls for.
+pub fn error(err: &Error) -> Response {
+    let code = err.code();
+    let body = json!({ "error": { "code": code.as_str(), "message": err.public_message() } });
+    warp::reply::with_status(warp::reply::json(&body), code.status()).into_response()
+}
+
+/// Turn what warp rejects into the same JSON errors as the handlers return.
+pub async fn recover(rejection: Rejection) -> std::result::Result<Response, Infallible> {
+    if let Some(Limited { retry_after }) = rejection.find() {
+        let mut response = error(&Error::RateLimited);
+        let retry_after = retry_after.as_secs().max(1);
+        response
+            .headers_mut()
+            .insert("retry-after", ::http::HeaderValue::from(retry_after));
+        return Ok(response);
+    }
+
+    let err = if rejection.is_not_found() {
+        Error::NotFound("no such endpoint".into())
+    } else if let Some(err) = rejection.find::<warp::reject::InvalidQuery>() {
+        Error::InvalidArgument(err.to_string())
+    } else if rejection.find::<warp::reject::MethodNotAllowed>().is_some() {
+        Error::InvalidArgument("only GET is supported".into())
+    } else {
+        return Ok(warp::reply::with_status(
+            "INTERNAL_SERVER_ERROR",
+            StatusCode::INTERNAL_SERVER_ERROR,
+        )
+        .into_response());
+    };
+    Ok(error(&err))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::SqliteStorage;
+    use crate::hn_client::HnClient;
+    use crate::rate_limit;
+    use serde_json::json;
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+    use std::str::FromStr;
+    use std::time::Duration;
+    use warp::hyper::body::Bytes;
+
+    /// Routes over an in-memory database holding a story with a comment and
+    /// two more stories, with HN itself standing in for a single user.
+    async fn setup() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        let options = SqliteConnectOptions::from_str("sqlite::memory:").unwrap();
+        let pool = SqlitePoolOptions::new().connect_lazy_with(options);
+        let storage = SqliteStorage::new(pool);
+        storage.migrate().await.unwrap();
+
+        let items = [
+            json!({"type": "story", "id": 1, "by": "dan", "time": 1_000, "title": "Rust 1.0", "kids": [4]}),
+            json!({"type": "story", "id": 2, "by": "dan", "time": 2_000, "title": "Go 1.0"}),
+            json!({"type": "story", "id": 3, "by": "bob", "time": 3_000, "title": "Rusty nails"}),
+            json!({"type": "comment", "id": 4, "by": "bob", "time": 4_000, "parent": 1, "text": "Hi"}),
+        ]
+        .into_iter()
+        .map(|item| serde_json::from_value::<Item>(item).unwrap().try_into().unwrap())
+        .collect::<Vec<_>>();
+        storage.insert_items(&items, None).await.unwrap();
+        storage.save_rank(&[3, 1, 2], Utc::now()).await.unwrap();
+
+        let hn = warp::path!("v0" / "user" / "dan.json").map(|| {
+            warp::reply::json(
+                &json!({"id": "dan", "created": 500, "karma": 7, "submitted": [2, 1]}),
+            )
+        });
+        let (addr, server) = warp::serve(hn).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        let client =
+            HnClient::with_base_url(&format!("http://{}/v0", addr), Duration::from_secs(5));
+
+        let storage: Arc<dyn Storage> = Arc::new(storage);
+        let store = Store::with_client(storage.clone(), client);
+        let config = Config::default();
+        routes(rate_limit::limit(config.clone()), store, storage, &config)
+    }
+
+    fn body(response: &HttpResponse<Bytes>) -> Value {
+        serde_json::from_slice(response.body()).unwrap()
+    }
+
+    fn ids(page: &Value) -> Vec<u64> {
+        page["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["id"].as_u64().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn serves_items_with_validators() {
+        let routes = setup().await;
+
+        let got = warp::test::request()
+            .path("/api/items/1")
+            .reply(&routes)
+            .await;
+        assert_eq!(got.status(), 200);
+        assert_eq!(body(&got)["title"], "Rust 1.0");
+        assert_eq!(
+            got.headers()["last-modified"],
+            "Thu, 01 Jan 1970 00:16:40 GMT"
+        );
+        let etag = got.headers()["etag"].to_str().unwrap().to_string();
+
+        let again = warp::test::request()
+            .path("/api/items/1")
+            .header("if-none-match", format!("W/{}", etag))
+            .reply(&routes)
+            .await;
+        assert_eq!(again.status(), 304);
+        assert!(again.body().is_empty());
+
+        let missing = warp::test::request()
+            .path("/api/items/99")
+            .reply(&routes)
+            .await;
+        assert_eq!(missing.status(), 404);
+        assert_eq!(body(&missing)["error"]["code"], "NOT_FOUND");
+    }
+
+    #[tokio::test]
+    async fn pages_lists_and_trees() {
+        let routes = setup().await;
+
+        let got = warp::test::request()
+            .path("/api/lists/top?offset=1&limit=5")
+            .reply(&routes)
+            .await;
+        assert_eq!(got.status(), 200);
+        let page = body(&got);
+        assert_eq!(ids(&page), vec![1, 2]);
+        assert_eq!(
+            (page["offset"].clone(), page["total"].clone()),
+            (json!(1), json!(3))
+        );
+
+        let unknown = warp::test::request()
+            .path("/api/lists/hot")
+            .reply(&routes)
+            .await;
+        assert_eq!(unknown.status(), 404);
+
+        let tree = warp::test::request()
+            .path("/api/items/1/tree")
+            .reply(&routes)
+            .await;
+        let tree = body(&tree);
+        assert_eq!(tree["item"]["id"], 1);
+        assert_eq!(ids(&tree["descendants"]), vec![4]);
+
+        let bad = warp::test::request()
+            .path("/api/lists/top?limit=lots")
+            .reply(&routes)
+            .await;
+        assert_eq!(bad.status(), 400);
+        assert_eq!(body(&bad)["error"]["code"], "INVALID_ARGUMENT");
+    }
+
+    #[tokio::test]
+    async fn searches_and_shows_users() {
+        let routes = setup().await;
+
+        let found = warp::test::request()
+            .path("/api/search?q=rust")
+            .reply(&routes)
+            .await;
+        assert_eq!(ids(&body(&found)), vec![3, 1]);
+
+        let empty = warp::test::request()
+            .path("/api/search?q=%20")
+            .reply(&routes)
+            .await;
+        assert_eq!(empty.status(), 400);
+
+        let user = warp::test::request()
+            .path("/api/users/dan?limit=1")
+            .reply(&routes)
+            .await;
+        assert_eq!(user.status(), 200);
+        let user = body(&user);
+        assert_eq!(user["user"]["karma"], 7);
+        assert_eq!(ids(&user["submitted"]), vec![2]);
+
+        let invalid = warp::test::request()
+            .path("/api/users/..%2Fitem%2F1")
+            .reply(&routes)
+            .await;
+        assert_eq!(invalid.status(), 400);
+    }
+}
@@ -1,12 +1,18 @@
 //! Errors, type aliases, and functions related to working with `Result`.
 
+use std::sync::Arc;
+
+use ::http::StatusCode;
+use async_graphql::ErrorExtensions;
 use thiserror::Error;
+use tracing::{error, warn};
 
 /// Result
 pub type Result<T> = std::result::Result<T, Error>;
 
 /// Represents all the ways that the client can fail.
 #[derive(Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     /// ReqwestError
     #[error(transparent)]
@@ -15,6 +21,130 @@ pub enum Error {
     GraphqlError(async_graphql::Error),
     #[error("database error")]
     DatabaseError(sqlx::Error),
+    #[error("migration failed: {0}")]
+    MigrateError(#[from] sqlx::migrate::MigrateError),
+    /// The database was migrated by a newer build than this one
+    #[error("database schema version {database} is newer than this build supports ({binary})")]
+    SchemaTooNew { database: i64, binary: i64 },
+    /// `DATABASE_URL` names a database this build can't use
+    #[error("unsupported database: {0}")]
+    UnsupportedDatabase(String),
+    /// Reading or writing outside the database, e.g. decompressing an item
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+    /// A stored record that can't be read back, e.g. an item whose JSON
+    /// doesn't parse
+    #[error("corrupt {table} record {id}: {reason}")]
+    CorruptRecord {
+        table: &'static str,
+        id: String,
+        reason: String,
+    },
+    /// Something asked for doesn't exist
+    #[error("{0}")]
+    NotFound(String),
+    /// The caller isn't allowed to do this
+    #[error("{0}")]
+    Unauthorized(String),
+    /// The caller, or we on its behalf, sent too many requests
+    #[error("too many requests, try again later")]
+    RateLimited,
+    /// The caller passed something we can't work with
+    #[error("{0}")]
+    InvalidArgument(String),
+    /// An error shared between several waiters, e.g. from a batched load
+    #[error(transparent)]
+    SharedError(Arc<Error>),
+}
+
+/// What kind of failure an error is, sent to GraphQL clients as
+/// `extensions.code` so they can react to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    NotFound,
+    Unauthorized,
+    UpstreamTimeout,
+    UpstreamUnavailable,
+    RateLimited,
+    InvalidArgument,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::NotFound => "NOT_FOUND",
+            ErrorCode::Unauthorized => "UNAUTHORIZED",
+            ErrorCode::UpstreamTimeout => "UPSTREAM_TIMEOUT",
+            ErrorCode::UpstreamUnavailable => "UPSTREAM_UNAVAILABLE",
+            ErrorCode::RateLimited => "RATE_LIMITED",
+            ErrorCode::InvalidArgument => "INVALID_ARGUMENT",
+            ErrorCode::Internal => "INTERNAL",
+        }
+    }
+
+    /// The status the REST API answers with.
+    pub fn status(self) -> StatusCode {
+        match self {
+            ErrorCode::NotFound => StatusCode::NOT_FOUND,
+            ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorCode::UpstreamTimeout => StatusCode::GATEWAY_TIMEOUT,
+            ErrorCode::UpstreamUnavailable => StatusCode::BAD_GATEWAY,
+            ErrorCode::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::InvalidArgument => StatusCode::BAD_REQUEST,
+            ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl Error {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::ReqwestError(err) if err.is_timeout() => ErrorCode::UpstreamTimeout,
+            Error::ReqwestError(_) => ErrorCode::UpstreamUnavailable,
+            Error::NotFound(_) => ErrorCode::NotFound,
+            Error::Unauthorized(_) => ErrorCode::Unauthorized,
+            Error::RateLimited => ErrorCode::RateLimited,
+            Error::InvalidArgument(_) => ErrorCode::InvalidArgument,
+            Error::SharedError(err) => err.code(),
+            Error::GraphqlError(_)
+            | Error::DatabaseError(_)
+            | Error::MigrateError(_)
+            | Error::SchemaTooNew { .. }
+            | Error::UnsupportedDatabase(_)
+            | Error::IoError(_)
+            | Error::CorruptRecord { .. } => ErrorCode::Internal,
+        }
+    }
+
+    /// What clients are told. Internal failures are logged here and reach
+    /// clients only as their code, so database and upstream details don't
+    /// leak.
+    pub fn public_message(&self) -> String {
+        match self.code() {
+            ErrorCode::Internal => {
+                error!(error = %self, "Internal error");
+                "internal error".to_string()
+            }
+            ErrorCode::UpstreamTimeout => {
+                warn!(error = %self, "Upstream timed out");
+                "Hacker News took too long to answer".to_string()
+            }
+            ErrorCode::UpstreamUnavailable => {
+                warn!(error = %self, "Upstream unavailable");
+                "Hacker News is unavailable".to_string()
+            }
+            _ => self.to_string(),
+        }
+    }
+
+    pub fn corrupt(table: &'static str, id: impl ToString, reason: impl ToString) -> Self {
+        Error::CorruptRecord {
+            table,
+            id: id.to_string(),
+            reason: reason.to_string(),
+        }
+    }
 }
 
 impl From<async_graphql::Error> for Error {
@@ -25,7 +155,70 @@ impl From<async_graphql::Error> for Error {
 
 impl From<sqlx::Error> for Error {
     fn from(err: sqlx::Error) -> Self {
-        dbg!(&err);
         Error::DatabaseError(err)
     }
 }
+
+impl From<Arc<Error>> for Error {
+    fn from(err: Arc<Error>) -> Self {
+        Error::SharedError(err)
+    }
+}
+
+impl ErrorExtensions for Error {
+    fn extend(&self) -> async_graphql::Error {
+        let code = self.code();
+        async_graphql::Error::new(self.public_message())
+            .extend_with(|_, e| e.set("code", code.as_str()))
+    }
+}
+
+/// What GraphQL resolvers return. `?` works as with [`Result`], but the
+/// error reaches clients through [`Error::extend`], with its code.
+pub type ResolverResult<T> = std::result::Result<T, ResolverError>;
+
+/// An [`Error`] on its way to a GraphQL client. It's deliberately not
+/// `Display`, which would pick async-graphql's plain conversion instead.
+#[derive(Debug, Clone)]
+pub struct ResolverError(Arc<Error>);
+
+impl<E: Into<Error>> From<E> for ResolverError {
+    fn from(err: E) -> Self {
+        ResolverError(Arc::new(err.into()))
+    }
+}
+
+impl From<ResolverError> for async_graphql::Error {
+    fn from(err: ResolverError) -> Self {
+        err.0.extend()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn code(err: &async_graphql::Error) -> Value {
+        serde_json::to_value(err).unwrap()["extensions"]["code"].clone()
+    }
+
+    #[test]
+    fn passes_on_messages_for_client_errors() {
+        let err = async_graphql::Error::from(ResolverError::from(Error::NotFound(
+            "item 1 has no revision 3".into(),
+        )));
+
+        assert_eq!(err.message, "item 1 has no revision 3");
+        assert_eq!(code(&err), json!("NOT_FOUND"));
+    }
+
+    #[test]
+    fn hides_internal_details() {
+        let shared = Arc::new(Error::corrupt("item", 1, "truncated"));
+        let err = async_graphql::Error::from(ResolverError::from(shared));
+
+        assert_eq!(err.message, "internal error");
+        assert_eq!(code(&err), json!("INTERNAL"));
+    }
+}
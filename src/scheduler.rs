@@ -0,0 +1,260 @@
+//! Runs background jobs independently on their own schedules and records how
+//! each run went in the `job_run` table.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+
+use chrono::Utc;
+use futures::future::{join_all, BoxFuture, FutureExt};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tokio::time::{sleep, timeout, Duration};
+use tracing::{error, info, info_span, Instrument};
+
+use crate::db::Storage;
+use crate::metrics::{JOB_DURATION, JOB_FAILURES};
+use crate::result::Result;
+use crate::shutdown::Shutdown;
+
+type JobFn = Arc<dyn Fn() -> BoxFuture<'static, Result<()>> + Send + Sync>;
+
+/// How and when a job runs. Durations are configured in seconds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobConfig {
+    pub enabled: bool,
+    /// Time between the end of one run and the start of the next.
+    #[serde(with = "secs")]
+    pub interval: Duration,
+    /// Up to this much extra time is randomly added to each interval.
+    #[serde(with = "secs")]
+    pub jitter: Duration,
+    /// Runs taking longer than this are cancelled and recorded as failed.
+    #[serde(with = "secs")]
+    pub timeout: Duration,
+}
+
+impl JobConfig {
+    pub fn every(interval: Duration) -> Self {
+        Self {
+            enabled: true,
+            interval,
+            jitter: Duration::from_secs(0),
+            timeout: Duration::from_secs(300),
+        }
+    }
+
+    pub fn disabled(self) -> Self {
+        Self {
+            enabled: false,
+            ..self
+        }
+    }
+}
+
+mod secs {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_secs())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_secs)
+    }
+}
+
+pub struct Scheduler {
+    storage: Arc<dyn Storage>,
+    jobs: Vec<(String, JobConfig, JobFn)>,
+}
+
+impl Scheduler {
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self {
+            storage,
+            jobs: vec![],
+        }
+    }
+
+    pub fn job<F, Fut>(mut self, name: &str, config: JobConfig, job: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let job: JobFn = Arc::new(move || job().boxed());
+        self.jobs.push((name.to_string(), config, job));
+        self
+    }
+
+    /// Run every enabled job, each on its own schedule, until shutdown.
+    /// A run in progress is allowed to finish.
+    pub async fn run(self, shutdown: Shutdown) {
+        let storage = self.storage;
+        let jobs = self
+            .jobs
+            .into_iter()
+            .filter(|(_, config, _)| config.enabled)
+            .map(|(name, config, job)| {
+                let storage = storage.clone();
+                let shutdown = shutdown.clone();
+                info!(job = %name, interval = ?config.interval, "Scheduling job");
+
+                tokio::spawn(async move {
+                    loop {
+                        run_once(&*storage, &name, &config, &job).await;
+
+                        let jitter = match config.jitter.as_millis() as u64 {
+                            0 => 0,
+                            max => rand::thread_rng().gen_range(0..=max),
+                        };
+                        tokio::select! {
+                            _ = sleep(config.interval + Duration::from_millis(jitter)) => {},
+                            _ = shutdown.wait() => break,
+                        }
+                    }
+                })
+            });
+
+        join_all(jobs).await;
+    }
+}
+
+/// Run a job once and record the outcome.
+async fn run_once(storage: &dyn Storage, name: &str, config: &JobConfig, job: &JobFn) {
+    let started_at = Utc::now();
+    let started = Instant::now();
+
+    let span = info_span!("job", job = name);
+    let error = match timeout(config.timeout, job().instrument(span.clone())).await {
+        Ok(Ok(())) => None,
+        Ok(Err(err)) => Some(format!("{:?}", err)),
+        Err(_) => Some(format!("timed out after {:?}", config.timeout)),
+    };
+
+    let elapsed = started.elapsed();
+    let duration_ms = elapsed.as_millis() as i64;
+    JOB_DURATION
+        .with_label_values(&[name])
+        .observe(elapsed.as_secs_f64());
+    if error.is_some() {
+        JOB_FAILURES.with_label_values(&[name]).inc();
+    }
+    span.in_scope(|| match &error {
+        Some(error) => error!(duration_ms, %error, "Job failed"),
+        None => info!(duration_ms, "Job finished"),
+    });
+
+    let result = storage
+        .record_job_run(name, started_at, duration_ms, error.as_deref())
+        .await;
+
+    if let Err(err) = result {
+        error!(job = name, error = ?err, "Couldn't record job run");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{run_once, JobConfig, JobFn, Scheduler};
+    use crate::db::{SqliteStorage, Storage};
+    use crate::result::Error;
+    use crate::shutdown::Shutdown;
+    use futures::FutureExt;
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+    use std::str::FromStr;
+    use std::sync::Arc;
+    use tokio::time::{sleep, timeout, Duration};
+
+    async fn setup() -> Arc<dyn Storage> {
+        let options = SqliteConnectOptions::from_str("sqlite::memory:").unwrap();
+        let pool = SqlitePoolOptions::new().connect_lazy_with(options);
+        sqlx::migrate!().run(&pool).await.unwrap();
+        Arc::new(SqliteStorage::new(pool))
+    }
+
+    async fn last_run(storage: &Arc<dyn Storage>) -> (Option<String>, bool) {
+        let run = storage
+            .job_runs()
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|run| run.name == "test")
+            .unwrap();
+        (run.last_error, run.last_success_at.is_some())
+    }
+
+    #[tokio::test]
+    async fn records_successful_runs() {
+        let storage = setup().await;
+        let job: JobFn = Arc::new(|| async { Ok(()) }.boxed());
+
+        run_once(
+            &*storage,
+            "test",
+            &JobConfig::every(Duration::from_secs(1)),
+            &job,
+        )
+        .await;
+
+        assert_eq!(last_run(&storage).await, (None, true));
+    }
+
+    #[tokio::test]
+    async fn records_errors_and_keeps_last_success() {
+        let storage = setup().await;
+        let config = JobConfig::every(Duration::from_secs(1));
+        let ok: JobFn = Arc::new(|| async { Ok(()) }.boxed());
+        let failing: JobFn =
+            Arc::new(|| async { Err(Error::DatabaseError(sqlx::Error::RowNotFound)) }.boxed());
+
+        run_once(&*storage, "test", &config, &ok).await;
+        run_once(&*storage, "test", &config, &failing).await;
+
+        let (error, succeeded) = last_run(&storage).await;
+        assert!(error.unwrap().contains("RowNotFound"));
+        assert!(succeeded);
+    }
+
+    #[tokio::test]
+    async fn records_timeouts() {
+        let storage = setup().await;
+        let config = JobConfig {
+            timeout: Duration::from_millis(10),
+            ..JobConfig::every(Duration::from_secs(1))
+        };
+        let slow: JobFn = Arc::new(|| {
+            async {
+                sleep(Duration::from_secs(5)).await;
+                Ok(())
+            }
+            .boxed()
+        });
+
+        run_once(&*storage, "test", &config, &slow).await;
+
+        let (error, succeeded) = last_run(&storage).await;
+        assert!(error.unwrap().starts_with("timed out"));
+        assert!(!succeeded);
+    }
+
+    #[tokio::test]
+    async fn stops_on_shutdown() {
+        let storage = setup().await;
+        let (sender, shutdown) = Shutdown::new();
+        let scheduler = Scheduler::new(storage.clone()).job(
+            "test",
+            JobConfig::every(Duration::from_secs(60)),
+            || async { Ok(()) },
+        );
+
+        let running = tokio::spawn(scheduler.run(shutdown));
+        sleep(Duration::from_millis(50)).await;
+        sender.send(true).unwrap();
+
+        let stopped = timeout(Duration::from_secs(1), running).await;
+        assert!(stopped.is_ok());
+        assert_eq!(last_run(&storage).await, (None, true));
+    }
+}
@@ -1,135 +1,274 @@
 use ammonia::clean;
+use async_graphql::dataloader::DataLoader;
 use async_graphql::*;
 use chrono::{DateTime, NaiveDateTime, Utc};
-use sqlx::SqlitePool;
+use futures::{Stream, StreamExt};
+use std::io::BufWriter;
+use std::sync::Arc;
+use tracing::{error, info};
 
 pub struct QueryRoot;
 
+/// What walking a thread costs towards `max_query_complexity`: what's asked
+/// of each item times roughly how many items a field loads. Descendants can
+/// run to `max_descendants`, so nesting them is what the limit is for.
+const CHILDREN_COST: usize = 20;
+const DESCENDANTS_COST: usize = 100;
+const ANCESTORS_COST: usize = 10;
+/// What a list costs per item asked of it, when no `limit` is given: the
+/// default `max_list_items`.
+const LIST_COST: usize = 50;
+
 use crate::{
-    domain::{comment::Comment, job::Job, story::Story, Item},
-    result::Result,
+    config::Config,
+    db::{self, Storage},
+    domain::{comment::Comment, job::Job, story::Story, Item, User},
+    events::{Event, Events},
+    export::{self, Filter, Format, Table},
+    guard::AdminGuard,
+    loader::{BookmarkLoader, ItemLoader},
+    result::{self, ResolverError, ResolverResult},
     store::Store,
 };
 
 #[Object]
 impl QueryRoot {
-    async fn top_items(&self, ctx: &Context<'_>, limit: Option<u32>) -> Result<Vec<Item>> {
-        let store = ctx.data::<Store>()?;
-        let pool = ctx.data::<SqlitePool>()?;
-
-        // Get top items
-        let ids = sqlx::query!(
-            r#"
-            SELECT 
-                item_id 
-            FROM 
-                list
-            WHERE
-                key = 'top_stories'
-            ORDER BY 
-               ordering ASC
-            "#,
-        )
-        .fetch_all(&*pool)
-        .await?
-        .into_iter()
-        .map(|row| row.item_id as u32)
-        .collect::<Vec<u32>>();
-
-        load_many(&store, ids, limit).await
+    #[graphql(complexity = "limit.map_or(LIST_COST, |limit| limit as usize) * child_complexity")]
+    async fn top_items(&self, ctx: &Context<'_>, limit: Option<u32>) -> ResolverResult<Vec<Item>> {
+        let storage = ctx.data::<Arc<dyn Storage>>()?;
+        let ids = storage.list("top_stories").await?;
+        load_many(ctx, ids, limit).await
     }
 
-    async fn ask_items(&self, ctx: &Context<'_>, limit: Option<u32>) -> Result<Vec<Item>> {
+    #[graphql(complexity = "limit.map_or(LIST_COST, |limit| limit as usize) * child_complexity")]
+    async fn ask_items(&self, ctx: &Context<'_>, limit: Option<u32>) -> ResolverResult<Vec<Item>> {
         let store = ctx.data::<Store>()?;
         let ids = store.get_ask_stories().await?;
-        load_many(&store, ids, limit).await
+        load_many(ctx, ids, limit).await
     }
 
-    async fn job_items(&self, ctx: &Context<'_>, limit: Option<u32>) -> Result<Vec<Item>> {
+    #[graphql(complexity = "limit.map_or(LIST_COST, |limit| limit as usize) * child_complexity")]
+    async fn job_items(&self, ctx: &Context<'_>, limit: Option<u32>) -> ResolverResult<Vec<Item>> {
         let store = ctx.data::<Store>()?;
         let ids = store.get_job_stories().await?;
-        load_many(&store, ids, limit).await
+        load_many(ctx, ids, limit).await
     }
 
-    async fn best_items(&self, ctx: &Context<'_>, limit: Option<u32>) -> Result<Vec<Item>> {
+    #[graphql(complexity = "limit.map_or(LIST_COST, |limit| limit as usize) * child_complexity")]
+    async fn best_items(&self, ctx: &Context<'_>, limit: Option<u32>) -> ResolverResult<Vec<Item>> {
         let store = ctx.data::<Store>()?;
         let ids = store.get_best_stories().await?;
-        load_many(&store, ids, limit).await
+        load_many(ctx, ids, limit).await
     }
 
-    async fn new_items(&self, ctx: &Context<'_>, limit: Option<u32>) -> Result<Vec<Item>> {
+    #[graphql(complexity = "limit.map_or(LIST_COST, |limit| limit as usize) * child_complexity")]
+    async fn new_items(&self, ctx: &Context<'_>, limit: Option<u32>) -> ResolverResult<Vec<Item>> {
         let store = ctx.data::<Store>()?;
         let ids = store.get_new_stories().await?;
-        load_many(&store, ids, limit).await
+        load_many(ctx, ids, limit).await
     }
 
-    async fn show_items(&self, ctx: &Context<'_>, limit: Option<u32>) -> Result<Vec<Item>> {
+    #[graphql(complexity = "limit.map_or(LIST_COST, |limit| limit as usize) * child_complexity")]
+    async fn show_items(&self, ctx: &Context<'_>, limit: Option<u32>) -> ResolverResult<Vec<Item>> {
         let store = ctx.data::<Store>()?;
         let ids = store.get_show_stories().await?;
-        load_many(&store, ids, limit).await
+        load_many(ctx, ids, limit).await
     }
 
-    async fn item_by_id(&self, ctx: &Context<'_>, id: u32) -> Result<Option<Item>> {
-        let store = ctx.data::<Store>()?;
-        store.get_item(id).await
+    async fn item_by_id(&self, ctx: &Context<'_>, id: u32) -> ResolverResult<Option<Item>> {
+        let loader = ctx.data::<DataLoader<ItemLoader>>()?;
+        Ok(loader.load_one(id).await?)
     }
 
-    async fn bookmarked_items(&self, ctx: &Context<'_>, _limit: Option<u32>) -> Result<Vec<Item>> {
+    /// Stored stories and jobs whose title contains `query`, newest first.
+    #[graphql(complexity = "limit.map_or(LIST_COST, |limit| limit as usize) * child_complexity")]
+    async fn search(
+        &self,
+        ctx: &Context<'_>,
+        query: String,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> ResolverResult<Vec<Item>> {
+        let max = ctx.data::<Config>()?.max_list_items;
+        let limit = limit.unwrap_or(max).min(max);
         let store = ctx.data::<Store>()?;
-        let pool = ctx.data::<SqlitePool>()?;
-
-        // Get bookmarked ids
-        let ids = sqlx::query!(
-            r#"
-            SELECT 
-                item_id 
-            FROM 
-                bookmarked_item
-            ORDER BY 
-                created_at DESC;
-            "#,
-        )
-        .fetch_all(&*pool)
-        .await?
-        .into_iter()
-        .map(|row| row.item_id as u32)
-        .collect::<Vec<u32>>();
-
-        load_many(&store, ids, None).await
-    }
-
-    async fn stats(&self, ctx: &Context<'_>) -> Result<String> {
-        let pool = ctx.data::<SqlitePool>()?;
-
-        // Get bookmarked ids
-        let (backfill_ptr,): (String,) = sqlx::query_as(
-            r#"
-            SELECT 
-                value 
-            FROM 
-                config
-            WHERE 
-                key='backfill_ptr'
-            "#,
-        )
-        .fetch_one(&*pool)
-        .await?;
 
-        Ok(backfill_ptr)
+        Ok(store.search(&query, limit, offset.unwrap_or(0)).await?)
     }
-}
 
-async fn load_many(store: &Store, ids: Vec<u32>, limit: Option<u32>) -> Result<Vec<Item>> {
-    let limit = limit.unwrap_or(50);
-    let limit = limit.min(50);
+    async fn user(&self, ctx: &Context<'_>, name: String) -> ResolverResult<Option<User>> {
+        let store = ctx.data::<Store>()?;
+        Ok(store.get_user(&name).await?)
+    }
+
+    #[graphql(complexity = "LIST_COST * child_complexity")]
+    async fn bookmarked_items(
+        &self,
+        ctx: &Context<'_>,
+        _limit: Option<u32>,
+    ) -> ResolverResult<Vec<Item>> {
+        let storage = ctx.data::<Arc<dyn Storage>>()?;
+        let ids = storage.bookmarked_ids().await?;
+        load_many(ctx, ids, None).await
+    }
+
+    /// How far back the backfill has reached. See `databaseStats` for more.
+    async fn stats(&self, ctx: &Context<'_>) -> ResolverResult<String> {
+        let stats = ctx.data::<Arc<dyn Storage>>()?.stats().await?;
+        let backfill_ptr = stats
+            .backfill_ptr
+            .ok_or_else(|| result::Error::NotFound("the backfill hasn't started".into()))?;
+        Ok(backfill_ptr.to_string())
+    }
+
+    /// Item counts, the backfill pointer and the schema version.
+    async fn database_stats(&self, ctx: &Context<'_>) -> ResolverResult<DatabaseStats> {
+        let stats = ctx.data::<Arc<dyn Storage>>()?.stats().await?;
+
+        Ok(DatabaseStats {
+            item_count: stats.item_count,
+            min_item_id: stats.min_item_id,
+            max_item_id: stats.max_item_id,
+            backfill_ptr: stats.backfill_ptr,
+            schema_version: stats.schema_version,
+        })
+    }
+
+    /// The latest edits to story titles and URLs, newest first.
+    #[graphql(complexity = "limit.map_or(LIST_COST, |limit| limit as usize) * child_complexity")]
+    async fn recent_title_changes(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<u32>,
+    ) -> ResolverResult<Vec<TitleChange>> {
+        let max = ctx.data::<Config>()?.max_list_items;
+        let limit = limit.unwrap_or(max).min(max);
+        let storage = ctx.data::<Arc<dyn Storage>>()?;
+        let changes = storage.recent_title_changes(limit).await?;
+
+        Ok(changes.into_iter().map(TitleChange::from).collect())
+    }
+
+    /// Stored items that couldn't be read back and were set aside, newest
+    /// first.
+    #[graphql(guard = "AdminGuard")]
+    async fn quarantined_items(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<u32>,
+    ) -> ResolverResult<Vec<QuarantinedItem>> {
+        let max = ctx.data::<Config>()?.max_list_items;
+        let limit = limit.unwrap_or(max).min(max);
+        let storage = ctx.data::<Arc<dyn Storage>>()?;
+        let items = storage.quarantined_items(limit).await?;
+
+        Ok(items.into_iter().map(QuarantinedItem::from).collect())
+    }
+
+    async fn jobs(&self, ctx: &Context<'_>) -> ResolverResult<Vec<JobRun>> {
+        let storage = ctx.data::<Arc<dyn Storage>>()?;
+        let runs = storage.job_runs().await?;
+
+        Ok(runs.into_iter().map(JobRun::from).collect())
+    }
+}
 
-    let mut items = store
-        .get_items(ids.clone().into_iter().take(limit as usize).collect())
+async fn load_many(
+    ctx: &Context<'_>,
+    ids: Vec<u32>,
+    limit: Option<u32>,
+) -> ResolverResult<Vec<Item>> {
+    let loader = ctx.data::<DataLoader<ItemLoader>>()?;
+    let max = ctx.data::<Config>()?.max_list_items;
+    let limit = limit.unwrap_or(max);
+    let limit = limit.min(max);
+
+    let mut items = loader
+        .load_many(ids.clone().into_iter().take(limit as usize))
         .await?;
 
     Ok(ids.into_iter().filter_map(|id| items.remove(&id)).collect())
 }
 
+/// A version of an item's content.
+#[derive(SimpleObject)]
+struct ItemRevision {
+    revision: i64,
+    /// When this version was first seen. For the first revision, when the
+    /// item was posted.
+    created_at: NaiveDateTime,
+    /// The item as HN sent it, in JSON.
+    original: String,
+}
+
+/// A field that differs between two revisions, with its values as JSON.
+/// A missing value means the field is missing from that revision.
+#[derive(SimpleObject)]
+struct FieldChange {
+    field: String,
+    before: Option<Json<serde_json::Value>>,
+    after: Option<Json<serde_json::Value>>,
+}
+
+async fn revisions(ctx: &Context<'_>, id: u32) -> ResolverResult<Vec<ItemRevision>> {
+    let storage = ctx.data::<Arc<dyn Storage>>()?;
+    let revisions = storage.item_revisions(id).await?;
+
+    Ok(revisions.into_iter().map(ItemRevision::from).collect())
+}
+
+async fn diff(ctx: &Context<'_>, id: u32, from: i64, to: i64) -> ResolverResult<Vec<FieldChange>> {
+    let storage = ctx.data::<Arc<dyn Storage>>()?;
+    let revisions = storage.item_revisions(id).await?;
+    let find = |number: i64| {
+        revisions
+            .iter()
+            .find(|revision| revision.revision == number)
+            .ok_or_else(|| {
+                result::Error::NotFound(format!("item {} has no revision {}", id, number))
+            })
+    };
+    let (from, to) = (find(from)?, find(to)?);
+
+    Ok(db::revision::diff(from, to)
+        .into_iter()
+        .map(FieldChange::from)
+        .collect())
+}
+
+/// An edit to a story's title or URL, e.g. by a moderator.
+#[derive(SimpleObject)]
+#[graphql(complex)]
+struct TitleChange {
+    item_id: i64,
+    old_title: String,
+    new_title: String,
+    old_url: Option<String>,
+    new_url: Option<String>,
+    /// When the change was noticed.
+    created_at: NaiveDateTime,
+}
+
+#[ComplexObject]
+impl TitleChange {
+    async fn item(&self, ctx: &Context<'_>) -> ResolverResult<Option<Item>> {
+        let loader = ctx.data::<DataLoader<ItemLoader>>()?;
+        Ok(loader.load_one(self.item_id as u32).await?)
+    }
+}
+
+/// A stored item that couldn't be read back. It's fetched from HN again the
+/// next time it's asked for.
+#[derive(SimpleObject)]
+struct QuarantinedItem {
+    item_id: i64,
+    codec: Option<String>,
+    /// The stored JSON, if it still decodes.
+    original: Option<String>,
+    reason: String,
+    created_at: NaiveDateTime,
+}
+
 #[derive(SimpleObject)]
 struct ItemMetric {
     item_id: i64,
@@ -138,11 +277,93 @@ struct ItemMetric {
     created_at: NaiveDateTime,
 }
 
+/// How a background job last went.
 #[derive(SimpleObject)]
-struct Stats {
+struct JobRun {
+    name: String,
+    last_run_at: NaiveDateTime,
+    last_duration_ms: i64,
+    last_error: Option<String>,
+    last_success_at: Option<NaiveDateTime>,
+}
+
+impl From<db::ItemMetric> for ItemMetric {
+    fn from(metric: db::ItemMetric) -> Self {
+        Self {
+            item_id: metric.item_id,
+            metric: metric.metric,
+            value: metric.value,
+            created_at: metric.created_at,
+        }
+    }
+}
+
+impl From<db::TitleChange> for TitleChange {
+    fn from(change: db::TitleChange) -> Self {
+        Self {
+            item_id: change.item_id,
+            old_title: change.old_title,
+            new_title: change.new_title,
+            old_url: change.old_url,
+            new_url: change.new_url,
+            created_at: change.created_at,
+        }
+    }
+}
+
+impl From<db::QuarantinedItem> for QuarantinedItem {
+    fn from(item: db::QuarantinedItem) -> Self {
+        Self {
+            original: item.json(),
+            item_id: item.item_id,
+            codec: item.codec,
+            reason: item.reason,
+            created_at: item.created_at,
+        }
+    }
+}
+
+impl From<db::ItemRevision> for ItemRevision {
+    fn from(revision: db::ItemRevision) -> Self {
+        Self {
+            revision: revision.revision,
+            created_at: revision.created_at,
+            original: revision.original,
+        }
+    }
+}
+
+impl From<db::revision::Change> for FieldChange {
+    fn from(change: db::revision::Change) -> Self {
+        Self {
+            field: change.field,
+            before: change.before.map(Json),
+            after: change.after.map(Json),
+        }
+    }
+}
+
+impl From<db::storage::JobRun> for JobRun {
+    fn from(run: db::storage::JobRun) -> Self {
+        Self {
+            name: run.name,
+            last_run_at: run.last_run_at,
+            last_duration_ms: run.last_duration_ms,
+            last_error: run.last_error,
+            last_success_at: run.last_success_at,
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+struct DatabaseStats {
     item_count: i64,
-    min_item_id: i64,
-    max_item_id: i64,
+    min_item_id: Option<i64>,
+    max_item_id: Option<i64>,
+    /// How far back the backfill has reached.
+    backfill_ptr: Option<i64>,
+    /// The newest migration applied to the database.
+    schema_version: Option<i64>,
 }
 
 #[Object]
@@ -183,10 +404,11 @@ impl Story {
         &self.time
     }
 
-    async fn children(&self, ctx: &Context<'_>) -> Result<Vec<Item>> {
-        let store = ctx.data::<Store>()?;
+    #[graphql(complexity = "CHILDREN_COST * child_complexity")]
+    async fn children(&self, ctx: &Context<'_>) -> ResolverResult<Vec<Item>> {
+        let loader = ctx.data::<DataLoader<ItemLoader>>()?;
         let kids = self.kids.clone().unwrap_or_default();
-        let mut items = store.get_items(kids.clone()).await?;
+        let mut items = loader.load_many(kids.clone()).await?;
 
         Ok(kids
             .into_iter()
@@ -194,62 +416,64 @@ impl Story {
             .collect())
     }
 
-    async fn descendants(&self, ctx: &Context<'_>) -> Result<Vec<Item>> {
+    #[graphql(complexity = "DESCENDANTS_COST * child_complexity")]
+    async fn descendants(&self, ctx: &Context<'_>) -> ResolverResult<Vec<Item>> {
         let store = ctx.data::<Store>()?;
         let items = store.get_descendants(self.id).await?;
 
-        Ok(items.into_iter().map(|(_, item)| item).collect())
+        Ok(items.into_values().collect())
     }
 
     async fn safe_text(&self) -> String {
-        clean(&self.text.clone().unwrap_or("".into()))
+        clean(&self.text.clone().unwrap_or_default())
     }
 
     async fn human_time(&self) -> String {
-        chrono_humanize::HumanTime::from(self.time.clone()).to_string()
-    }
-
-    async fn rank(&self, ctx: &Context<'_>) -> Result<Vec<ItemMetric>> {
-        let pool = ctx.data::<SqlitePool>()?;
-        let metrics = sqlx::query_as!(
-            ItemMetric,
-            r#"
-            SELECT 
-                * 
-            FROM 
-                item_metric
-            WHERE
-                item_id = ?1
-            ORDER BY 
-                created_at DESC
-            "#,
-            self.id
-        )
-        .fetch_all(pool)
-        .await?;
+        chrono_humanize::HumanTime::from(self.time).to_string()
+    }
 
-        Ok(metrics)
-    }
-
-    async fn is_bookmarked(&self, ctx: &Context<'_>) -> Result<bool> {
-        let pool = ctx.data::<SqlitePool>()?;
-
-        // Get bookmarked ids
-        let is_bookmarked = sqlx::query!(
-            r#"
-            SELECT 
-                item_id 
-            FROM 
-                bookmarked_item
-            WHERE
-                item_id = ?1
-            "#,
-            self.id
-        )
-        .fetch_optional(&*pool)
-        .await?;
+    async fn rank(&self, ctx: &Context<'_>) -> ResolverResult<Vec<ItemMetric>> {
+        let storage = ctx.data::<Arc<dyn Storage>>()?;
+        let metrics = storage.item_metrics(self.id).await?;
+
+        Ok(metrics.into_iter().map(ItemMetric::from).collect())
+    }
+
+    /// Edits to the title or URL, oldest first.
+    async fn title_history(&self, ctx: &Context<'_>) -> ResolverResult<Vec<TitleChange>> {
+        let storage = ctx.data::<Arc<dyn Storage>>()?;
+        let changes = storage.title_changes(self.id).await?;
+
+        Ok(changes.into_iter().map(TitleChange::from).collect())
+    }
+
+    async fn is_bookmarked(&self, ctx: &Context<'_>) -> ResolverResult<bool> {
+        let loader = ctx.data::<DataLoader<BookmarkLoader>>()?;
+        Ok(loader.load_one(self.id).await?.unwrap_or_default())
+    }
+
+    async fn deleted(&self) -> bool {
+        self.deleted
+    }
+
+    async fn dead(&self) -> bool {
+        self.dead
+    }
 
-        Ok(is_bookmarked.is_some())
+    /// Earlier versions of the item and the current one, oldest first.
+    /// Empty if its content never changed.
+    async fn revisions(&self, ctx: &Context<'_>) -> ResolverResult<Vec<ItemRevision>> {
+        revisions(ctx, self.id).await
+    }
+
+    /// How the item's content changed between two revisions.
+    async fn diff(
+        &self,
+        ctx: &Context<'_>,
+        from: i64,
+        to: i64,
+    ) -> ResolverResult<Vec<FieldChange>> {
+        diff(ctx, self.id, from, to).await
     }
 }
 
@@ -279,10 +503,11 @@ impl Comment {
         &self.time
     }
 
-    async fn children(&self, ctx: &Context<'_>) -> Result<Vec<Item>> {
-        let store = ctx.data::<Store>()?;
+    #[graphql(complexity = "CHILDREN_COST * child_complexity")]
+    async fn children(&self, ctx: &Context<'_>) -> ResolverResult<Vec<Item>> {
+        let loader = ctx.data::<DataLoader<ItemLoader>>()?;
         let kids = self.kids.clone().unwrap_or_default();
-        let mut items = store.get_items(kids.clone()).await?;
+        let mut items = loader.load_many(kids.clone()).await?;
 
         Ok(kids
             .into_iter()
@@ -290,18 +515,20 @@ impl Comment {
             .collect())
     }
 
-    async fn ancestors(&self, ctx: &Context<'_>) -> Result<Vec<Item>> {
+    #[graphql(complexity = "ANCESTORS_COST * child_complexity")]
+    async fn ancestors(&self, ctx: &Context<'_>) -> ResolverResult<Vec<Item>> {
         let store = ctx.data::<Store>()?;
         let items = store.get_ancestors(self.id).await?;
 
-        Ok(items.into_iter().map(|(_, item)| item).collect())
+        Ok(items.into_values().collect())
     }
 
-    async fn descendants(&self, ctx: &Context<'_>) -> Result<Vec<Item>> {
+    #[graphql(complexity = "DESCENDANTS_COST * child_complexity")]
+    async fn descendants(&self, ctx: &Context<'_>) -> ResolverResult<Vec<Item>> {
         let store = ctx.data::<Store>()?;
         let items = store.get_descendants(self.id).await?;
 
-        Ok(items.into_iter().map(|(_, item)| item).collect())
+        Ok(items.into_values().collect())
     }
 
     async fn safe_text(&self) -> String {
@@ -309,28 +536,36 @@ impl Comment {
     }
 
     async fn human_time(&self) -> String {
-        chrono_humanize::HumanTime::from(self.time.clone()).to_string()
-    }
-
-    async fn is_bookmarked(&self, ctx: &Context<'_>) -> Result<bool> {
-        let pool = ctx.data::<SqlitePool>()?;
-
-        // Get bookmarked ids
-        let is_bookmarked = sqlx::query!(
-            r#"
-            SELECT 
-                item_id 
-            FROM 
-                bookmarked_item
-            WHERE
-                item_id = ?1
-            "#,
-            self.id
-        )
-        .fetch_optional(&*pool)
-        .await?;
+        chrono_humanize::HumanTime::from(self.time).to_string()
+    }
+
+    async fn is_bookmarked(&self, ctx: &Context<'_>) -> ResolverResult<bool> {
+        let loader = ctx.data::<DataLoader<BookmarkLoader>>()?;
+        Ok(loader.load_one(self.id).await?.unwrap_or_default())
+    }
 
-        Ok(is_bookmarked.is_some())
+    async fn deleted(&self) -> bool {
+        self.deleted
+    }
+
+    async fn dead(&self) -> bool {
+        self.dead
+    }
+
+    /// Earlier versions of the item and the current one, oldest first.
+    /// Empty if its content never changed.
+    async fn revisions(&self, ctx: &Context<'_>) -> ResolverResult<Vec<ItemRevision>> {
+        revisions(ctx, self.id).await
+    }
+
+    /// How the item's content changed between two revisions.
+    async fn diff(
+        &self,
+        ctx: &Context<'_>,
+        from: i64,
+        to: i64,
+    ) -> ResolverResult<Vec<FieldChange>> {
+        diff(ctx, self.id, from, to).await
     }
 }
 
@@ -365,7 +600,52 @@ impl Job {
     }
 
     async fn human_time(&self) -> String {
-        chrono_humanize::HumanTime::from(self.time.clone()).to_string()
+        chrono_humanize::HumanTime::from(self.time).to_string()
+    }
+
+    /// Earlier versions of the item and the current one, oldest first.
+    /// Empty if its content never changed.
+    async fn revisions(&self, ctx: &Context<'_>) -> ResolverResult<Vec<ItemRevision>> {
+        revisions(ctx, self.id).await
+    }
+
+    /// How the item's content changed between two revisions.
+    async fn diff(
+        &self,
+        ctx: &Context<'_>,
+        from: i64,
+        to: i64,
+    ) -> ResolverResult<Vec<FieldChange>> {
+        diff(ctx, self.id, from, to).await
+    }
+}
+
+#[Object]
+impl User {
+    async fn id(&self) -> &str {
+        &self.id
+    }
+
+    async fn created(&self) -> &DateTime<Utc> {
+        &self.created
+    }
+
+    async fn karma(&self) -> i64 {
+        self.karma
+    }
+
+    async fn about(&self) -> &Option<String> {
+        &self.about
+    }
+
+    async fn safe_about(&self) -> String {
+        clean(&self.about.clone().unwrap_or_default())
+    }
+
+    /// The user's stories, polls and comments, newest first.
+    #[graphql(complexity = "limit.map_or(LIST_COST, |limit| limit as usize) * child_complexity")]
+    async fn submitted(&self, ctx: &Context<'_>, limit: Option<u32>) -> ResolverResult<Vec<Item>> {
+        load_many(ctx, self.submitted.clone(), limit).await
     }
 }
 
@@ -374,47 +654,199 @@ pub struct MutationRoot;
 
 #[Object]
 impl MutationRoot {
-    async fn bookmark_item(&self, ctx: &Context<'_>, item_id: u32) -> Result<Option<Item>> {
-        let pool = ctx.data::<SqlitePool>()?;
-
-        // Insert a bookmarked item
-        let now = Utc::now();
-        let _ = sqlx::query!(
-            r#"
-        INSERT INTO 
-            bookmarked_item (item_id, user_id, created_at)
-        VALUES
-            (?1, "dan", ?2)
-        "#,
-            item_id,
-            now
-        )
-        .execute(&*pool)
-        .await?;
+    async fn bookmark_item(&self, ctx: &Context<'_>, item_id: u32) -> ResolverResult<Option<Item>> {
+        let storage = ctx.data::<Arc<dyn Storage>>()?;
+        storage.add_bookmark(item_id, "dan", Utc::now()).await?;
 
         let store = ctx.data::<Store>()?;
-        store.get_item(item_id).await
-    }
-
-    async fn unbookmark_item(&self, ctx: &Context<'_>, item_id: u32) -> Result<Option<Item>> {
-        let pool = ctx.data::<SqlitePool>()?;
-
-        // Insert a bookmarked item
-        let _ = sqlx::query!(
-            r#"
-            DELETE FROM 
-                bookmarked_item 
-            WHERE
-                item_id = ?1
-            AND
-                user_id = "dan"
-            "#,
-            item_id,
-        )
-        .execute(&*pool)
-        .await?;
+        Ok(store.get_item(item_id).await?)
+    }
+
+    async fn unbookmark_item(
+        &self,
+        ctx: &Context<'_>,
+        item_id: u32,
+    ) -> ResolverResult<Option<Item>> {
+        let storage = ctx.data::<Arc<dyn Storage>>()?;
+        storage.remove_bookmark(item_id, "dan").await?;
 
         let store = ctx.data::<Store>()?;
-        store.get_item(item_id).await
+        Ok(store.get_item(item_id).await?)
+    }
+
+    /// Start exporting a table into the export directory, returning the
+    /// file's name. The file is complete once the export is logged as done.
+    #[graphql(guard = "AdminGuard")]
+    async fn export(
+        &self,
+        ctx: &Context<'_>,
+        table: Table,
+        format: Format,
+        #[graphql(default)] filter: Filter,
+    ) -> ResolverResult<String> {
+        let pool = match ctx.data::<Arc<dyn Storage>>()?.sqlite() {
+            Some(pool) => pool.clone(),
+            None => {
+                return Err(result::Error::InvalidArgument(
+                    "exports only support SQLite so far".into(),
+                )
+                .into())
+            }
+        };
+        let dir = &ctx.data::<Config>()?.export_dir;
+
+        let (name, file) = export::create_file(dir, table, format)?;
+        let path = dir.join(&name);
+
+        tokio::spawn(async move {
+            let out = BufWriter::new(file);
+            match export::export(&pool, table, format, &filter, out).await {
+                Ok(written) => info!(path = %path.display(), written, "Export done"),
+                Err(err) => error!(path = %path.display(), error = %err, "Export failed"),
+            }
+        });
+
+        Ok(name)
+    }
+}
+
+// Subscriptions
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    async fn front_page_changed(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = "top_stories")] list: String,
+    ) -> Result<impl Stream<Item = Vec<Item>>, ResolverError> {
+        let events = ctx.data::<Events>()?;
+        let store = ctx.data::<Store>()?.clone();
+
+        Ok(events.subscribe().filter_map(move |event| {
+            let store = store.clone();
+            let list = list.clone();
+            async move {
+                match event {
+                    Event::ListChanged { key, ids } if key == list => {
+                        let mut items = store.get_items(ids.clone()).await.ok()?;
+                        Some(ids.into_iter().filter_map(|id| items.remove(&id)).collect())
+                    }
+                    _ => None,
+                }
+            }
+        }))
+    }
+
+    async fn item_updated(
+        &self,
+        ctx: &Context<'_>,
+        id: u32,
+    ) -> Result<impl Stream<Item = Item>, ResolverError> {
+        let events = ctx.data::<Events>()?;
+
+        Ok(events.subscribe().filter_map(move |event| async move {
+            match event {
+                Event::ItemUpdated(item) if item.id() == id => Some(item),
+                _ => None,
+            }
+        }))
+    }
+
+    async fn new_comments(
+        &self,
+        ctx: &Context<'_>,
+        story_id: u32,
+    ) -> Result<impl Stream<Item = Item>, ResolverError> {
+        let events = ctx.data::<Events>()?;
+
+        Ok(events.subscribe().filter_map(move |event| async move {
+            match event {
+                Event::NewComment {
+                    story_id: id,
+                    comment,
+                } if id == story_id => Some(comment),
+                _ => None,
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::SqliteStorage;
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+    use std::str::FromStr;
+
+    async fn errors(query: &str) -> Vec<String> {
+        let config = Config::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .limit_depth(config.max_query_depth)
+            .limit_complexity(config.max_query_complexity)
+            .finish();
+
+        schema
+            .execute(query)
+            .await
+            .errors
+            .into_iter()
+            .map(|err| err.message)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn rejects_nested_thread_walks() {
+        let nested = "{ topItems { ... on Story { descendants { ... on Comment { descendants { ... on Comment { id } } } } } } }";
+        assert_eq!(errors(nested).await, vec!["Query is too complex."]);
+
+        // Every listed story pays for its own walk
+        let shallow =
+            "{ topItems { ... on Story { descendants { ... on Comment { id text } } } } }";
+        assert_eq!(errors(shallow).await, vec!["Query is too complex."]);
+
+        // Fails for want of a database, but gets past the limits
+        let limited =
+            "{ topItems(limit: 5) { ... on Story { descendants { ... on Comment { id text } } } } }";
+        let got = errors(limited).await;
+        assert!(!got.is_empty());
+        assert!(!got.iter().any(|err| err.contains("too complex")));
+    }
+
+    #[tokio::test]
+    async fn rejects_deep_queries() {
+        let deep = format!(
+            "{{ recentTitleChanges {}{{ itemId }}{} }}",
+            "{ item { ... on Story { titleHistory ".repeat(6),
+            " } } }".repeat(6)
+        );
+        assert_eq!(errors(&deep).await, vec!["Query is nested too deep."]);
+    }
+
+    #[tokio::test]
+    async fn keeps_stats_a_string() {
+        let options = SqliteConnectOptions::from_str("sqlite::memory:").unwrap();
+        let pool = SqlitePoolOptions::new().connect_lazy_with(options);
+        let storage = SqliteStorage::new(pool);
+        storage.migrate().await.unwrap();
+        storage.set_config("backfill_ptr", "8863").await.unwrap();
+        let storage: Arc<dyn Storage> = Arc::new(storage);
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .data(storage)
+            .finish();
+
+        let response = schema
+            .execute("{ stats databaseStats { itemCount backfillPtr schemaVersion } }")
+            .await;
+
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let data = response.data.into_json().unwrap();
+        assert_eq!(data["stats"], "8863");
+        assert_eq!(data["databaseStats"]["itemCount"], 0);
+        assert_eq!(data["databaseStats"]["backfillPtr"], 8863);
+        assert_eq!(
+            data["databaseStats"]["schemaVersion"],
+            db::migration::latest()
+        );
     }
 }
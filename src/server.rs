@@ -0,0 +1,297 @@
+//! The HTTP server: GraphQL, the REST API, feeds, the playground and the
+//! operational endpoints, with the background jobs running alongside.
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use ::http::StatusCode;
+use async_graphql::dataloader::DataLoader;
+use async_graphql::extensions::apollo_persisted_queries::{
+    ApolloPersistedQueries, LruCacheStorage,
+};
+use async_graphql::http::{playground_source, GraphQLPlaygroundConfig};
+use async_graphql::*;
+use async_graphql_warp::{graphql_subscription, GraphQLBadRequest, GraphQLResponse};
+use futures::future::join_all;
+use tokio::time::timeout;
+use tracing::{info, info_span, warn, Instrument};
+use warp::{http::Method, http::Response as HttpResponse, Filter, Rejection, Reply};
+
+use crate::config::{Config, IngestMode};
+use crate::db::Storage;
+use crate::events::Events;
+use crate::guard::Admin;
+use crate::loader::{BookmarkLoader, ItemLoader};
+use crate::rate_limit::{self, Limited};
+use crate::response_cache::{Caching, ResponseCache};
+use crate::result::Error;
+use crate::schema::{MutationRoot, QueryRoot, SubscriptionRoot};
+use crate::shutdown::Shutdown;
+use crate::store::Store;
+use crate::{cron, feed, health, metrics, rest};
+
+/// A GraphQL error response telling the client to wait `retry_after`.
+fn limited(retry_after: Duration) -> warp::reply::Response {
+    let body = serde_json::json!({ "errors": [Error::RateLimited.extend()] });
+    let reply = warp::reply::with_status(warp::reply::json(&body), StatusCode::TOO_MANY_REQUESTS);
+    let retry_after = retry_after.as_secs().max(1).to_string();
+    warp::reply::with_header(reply, "retry-after", retry_after).into_response()
+}
+
+/// Serve until SIGINT or SIGTERM, then give in-flight work until the
+/// shutdown deadline to finish.
+pub async fn serve(config: Config, storage: Arc<dyn Storage>) {
+    let store = Store::new(storage.clone(), &config);
+    let events = Events::new(1024);
+    let persisted =
+        (config.persisted_queries > 0).then(|| LruCacheStorage::new(config.persisted_queries));
+    let cache = Arc::new(ResponseCache::new(
+        config.response_cache_ttl(),
+        persisted.clone(),
+    ));
+    tokio::spawn({
+        let cache = cache.clone();
+        let events = events.subscribe();
+        async move { cache.clear_on_list_changes(events).await }
+    });
+
+    let mut schema = Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+        .data(config.clone())
+        .data(store.clone())
+        .data(storage.clone())
+        .data(events.clone())
+        .data(DataLoader::new(
+            ItemLoader::new(store.clone()),
+            tokio::spawn,
+        ))
+        .data(DataLoader::new(
+            BookmarkLoader::new(storage.clone()),
+            tokio::spawn,
+        ))
+        .limit_depth(config.max_query_depth)
+        .limit_complexity(config.max_query_complexity);
+    if let Some(persisted) = persisted {
+        schema = schema.extension(ApolloPersistedQueries::new(persisted));
+    }
+    let schema = schema.finish();
+
+    // One allowance per client, whether it asks over GraphQL or REST
+    let limit = rate_limit::limit(config.clone());
+
+    // Only websocket upgrades are charged here, so other requests aren't
+    // charged twice on their way to their own route. Limited upgrades are
+    // answered here too, or they'd fall through to the playground.
+    let graphql_subscription = warp::header::exact_ignore_case("upgrade", "websocket")
+        .and(limit.clone())
+        .and(graphql_subscription(schema.clone()))
+        .recover(|err: Rejection| async move {
+            match err.find() {
+                Some(Limited { retry_after }) => Ok(limited(*retry_after)),
+                None => Err(err),
+            }
+        });
+
+    let api = rest::routes(limit.clone(), store.clone(), storage.clone(), &config);
+    let feeds = feed::routes(limit.clone(), store.clone(), storage.clone(), &config);
+
+    let graphql_post = limit
+        .and(warp::header::optional::<String>("x-admin-token"))
+        .and(async_graphql_warp::graphql(schema))
+        .and_then({
+            let config = config.clone();
+            let cache = cache.clone();
+            move |token: Option<String>,
+                  (schema, mut request): (
+                Schema<QueryRoot, MutationRoot, SubscriptionRoot>,
+                async_graphql::Request,
+            )| {
+                let admin = Admin::authorize(&config, token.as_deref());
+                let anonymous = admin.is_none();
+                if let Some(admin) = admin {
+                    request = request.data(admin);
+                }
+                let persisted = config.persisted_queries > 0
+                    && request.extensions.contains_key("persistedQuery");
+                execute(schema, cache.clone(), request, anonymous, persisted)
+            }
+        });
+
+    let graphql_playground = warp::path::end().and(warp::get()).map(|| {
+        HttpResponse::builder()
+            .header("content-type", "text/html")
+            .body(playground_source(
+                GraphQLPlaygroundConfig::new("/").subscription_endpoint("/"),
+            ))
+    });
+
+    let metrics = warp::path("metrics")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(|| {
+            HttpResponse::builder()
+                .header("content-type", "text/plain; version=0.0.4")
+                .body(metrics::render())
+        });
+
+    let healthz = warp::path("healthz")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(|| warp::reply::json(&serde_json::json!({ "status": "ok" })));
+
+    let readyz = warp::path("readyz")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and_then({
+            let storage = storage.clone();
+            let store = store.clone();
+            let config = config.clone();
+            move || {
+                let storage = storage.clone();
+                let store = store.clone();
+                let config = config.clone();
+                async move {
+                    let readiness = health::readiness(&*storage, &store, &config).await;
+                    let status = if readiness.is_ready() {
+                        StatusCode::OK
+                    } else {
+                        StatusCode::SERVICE_UNAVAILABLE
+                    };
+                    Ok::<_, Infallible>(warp::reply::with_status(
+                        warp::reply::json(&readiness),
+                        status,
+                    ))
+                }
+            }
+        });
+
+    let cors = warp::cors()
+        .allow_methods(&[Method::POST, Method::GET, Method::OPTIONS])
+        .allow_credentials(true)
+        .allow_headers(vec!["content-type", "X-Auth-Token", "X-Admin-Token"]);
+    let cors = if config.cors_origins.is_empty() {
+        cors.allow_any_origin()
+    } else {
+        cors.allow_origins(config.cors_origins.iter().map(String::as_str))
+    };
+
+    let routes = metrics
+        .or(healthz)
+        .or(readyz)
+        .or(graphql_subscription)
+        .or(graphql_playground)
+        .or(api)
+        .or(feeds)
+        .or(graphql_post)
+        .recover(|err: Rejection| async move {
+            if let Some(GraphQLBadRequest(err)) = err.find() {
+                return Ok::<_, Infallible>(
+                    warp::reply::with_status(err.to_string(), StatusCode::BAD_REQUEST)
+                        .into_response(),
+                );
+            }
+            if let Some(Limited { retry_after }) = err.find() {
+                return Ok(limited(*retry_after));
+            }
+
+            Ok(warp::reply::with_status(
+                "INTERNAL_SERVER_ERROR".to_string(),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            )
+            .into_response())
+        })
+        .with(cors)
+        .with(warp::trace::request());
+
+    let shutdown = Shutdown::on_signal();
+    let mut background = vec![];
+
+    let streaming = config.ingest_mode == IngestMode::Stream;
+    if streaming {
+        background.push(tokio::spawn(cron::start_streaming(
+            store.clone(),
+            storage.clone(),
+            events.clone(),
+            shutdown.clone(),
+        )));
+    }
+    background.push(tokio::spawn(
+        cron::scheduler(store, storage, events, &config).run(shutdown.clone()),
+    ));
+
+    // Stops accepting connections on shutdown, then drains in-flight requests
+    let addr = SocketAddr::new(config.bind_address, config.port);
+    let (addr, server) = warp::serve(routes).bind_with_graceful_shutdown(addr, {
+        let shutdown = shutdown.clone();
+        async move { shutdown.wait().await }
+    });
+    background.push(tokio::spawn(server));
+
+    info!("Playground: http://{}", addr);
+    shutdown.wait().await;
+
+    if timeout(config.shutdown_deadline(), join_all(background))
+        .await
+        .is_err()
+    {
+        warn!(
+            "Gave up waiting for in-flight work after {:?}",
+            config.shutdown_deadline()
+        );
+    }
+}
+
+/// Execute a GraphQL request, or answer it from the response cache,
+/// recording how it went. Only the span gets the operation name as sent;
+/// metrics get [`metrics::operation`].
+async fn execute(
+    schema: Schema<QueryRoot, MutationRoot, SubscriptionRoot>,
+    cache: Arc<ResponseCache>,
+    request: async_graphql::Request,
+    anonymous: bool,
+    persisted: bool,
+) -> Result<GraphQLResponse, Infallible> {
+    let name = request.operation_name.clone();
+    let operation = metrics::operation(name.as_deref(), persisted);
+    let span = info_span!(
+        "graphql",
+        operation = %name.as_deref().unwrap_or("anonymous")
+    );
+    let started = Instant::now();
+
+    let caching = cache.caching(&request, anonymous).await;
+    let execute = schema.execute(request).instrument(span.clone());
+    let response = match caching {
+        Caching::Key(key) => {
+            let (response, hit) = cache.get_or_execute(key, execute).await;
+            let result = if hit { "hit" } else { "miss" };
+            metrics::RESPONSE_CACHE_LOOKUPS
+                .with_label_values(&[result])
+                .inc();
+            response
+        }
+        Caching::Invalidates => {
+            let response = execute.await;
+            cache.clear();
+            response
+        }
+        Caching::Bypass => execute.await,
+    };
+
+    span.in_scope(|| {
+        info!(
+            duration_ms = started.elapsed().as_millis() as u64,
+            errors = response.errors.len(),
+            "executed operation"
+        )
+    });
+    metrics::GRAPHQL_OPERATIONS
+        .with_label_values(&[&operation])
+        .inc();
+    metrics::GRAPHQL_ERRORS
+        .with_label_values(&[&operation])
+        .inc_by(response.errors.len() as u64);
+
+    Ok(GraphQLResponse::from(response))
+}
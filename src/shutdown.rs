@@ -0,0 +1,74 @@
+use tokio::signal;
+use tokio::sync::watch;
+use tracing::info;
+
+/// Lets long-running work find out that the process is shutting down.
+#[derive(Clone)]
+pub struct Shutdown {
+    receiver: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    /// A shutdown triggered through the returned sender, or by dropping it.
+    pub fn new() -> (watch::Sender<bool>, Self) {
+        let (sender, receiver) = watch::channel(false);
+        (sender, Self { receiver })
+    }
+
+    /// A shutdown triggered by SIGINT or SIGTERM.
+    pub fn on_signal() -> Self {
+        let (sender, shutdown) = Self::new();
+
+        tokio::spawn(async move {
+            wait_for_signal().await;
+            info!("Shutting down...");
+            let _ = sender.send(true);
+        });
+
+        shutdown
+    }
+
+    /// Resolves once shutdown has started.
+    pub async fn wait(&self) {
+        let mut receiver = self.receiver.clone();
+        while !*receiver.borrow() {
+            if receiver.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate())
+        .expect("failed to listen for SIGTERM");
+
+    tokio::select! {
+        _ = signal::ctrl_c() => {},
+        _ = terminate.recv() => {},
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    let _ = signal::ctrl_c().await;
+}
+
+#[cfg(test)]
+mod test {
+    use super::Shutdown;
+    use tokio::time::{timeout, Duration};
+
+    #[tokio::test]
+    async fn waits_until_triggered() {
+        let (sender, shutdown) = Shutdown::new();
+
+        let waiting = timeout(Duration::from_millis(10), shutdown.wait()).await;
+        assert!(waiting.is_err());
+
+        sender.send(true).unwrap();
+        let waiting = timeout(Duration::from_millis(10), shutdown.wait()).await;
+        assert!(waiting.is_ok());
+    }
+}
@@ -0,0 +1,217 @@
+//! A minimal Server-Sent Events parser and the Firebase streaming protocol
+//! built on top of it.
+
+use serde::Deserialize;
+use serde_json::Value;
+
+/// A single dispatched SSE event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SseEvent {
+    pub event: String,
+    pub data: String,
+}
+
+/// Turns chunks of an SSE response body into events.
+#[derive(Debug, Default)]
+pub struct SseParser {
+    /// Bytes of the current line, kept undecoded as a chunk can end in the
+    /// middle of a character.
+    buffer: Vec<u8>,
+    event: Option<String>,
+    data: Vec<String>,
+}
+
+impl SseParser {
+    /// Feed a chunk of the body, returning any events it completed.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut events = vec![];
+        while let Some(end) = self.buffer.iter().position(|&byte| byte == b'\n') {
+            let line = String::from_utf8_lossy(&self.buffer[..end])
+                .trim_end_matches('\r')
+                .to_string();
+            self.buffer.drain(..=end);
+
+            if line.is_empty() {
+                if !self.data.is_empty() || self.event.is_some() {
+                    events.push(SseEvent {
+                        event: self.event.take().unwrap_or_else(|| "message".into()),
+                        data: self.data.join("\n"),
+                    });
+                    self.data.clear();
+                }
+                continue;
+            }
+
+            if line.starts_with(':') {
+                continue;
+            }
+
+            let (field, value) = match line.split_once(':') {
+                Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+                None => (line.as_str(), ""),
+            };
+
+            match field {
+                "event" => self.event = Some(value.to_string()),
+                "data" => self.data.push(value.to_string()),
+                _ => {}
+            }
+        }
+
+        events
+    }
+}
+
+/// What a Firebase event means for the watcher.
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    /// The watched value changed.
+    Updated,
+    /// Nothing changed, e.g. a keep-alive.
+    Unchanged,
+    /// The server closed the stream on us and we should reconnect.
+    Cancelled,
+}
+
+#[derive(Debug, Deserialize)]
+struct Payload {
+    path: String,
+    data: Value,
+}
+
+/// Apply a Firebase `put`/`patch` event to our copy of the watched value.
+pub fn apply(state: &mut Value, event: &SseEvent) -> Change {
+    match event.event.as_str() {
+        "put" | "patch" => {
+            let payload = match serde_json::from_str::<Payload>(&event.data) {
+                Ok(payload) => payload,
+                Err(_) => return Change::Unchanged,
+            };
+
+            let target = pointer_mut(state, &payload.path);
+            if event.event == "put" {
+                *target = payload.data;
+            } else if let Value::Object(fields) = payload.data {
+                for (key, value) in fields {
+                    *pointer_mut(target, &key) = value;
+                }
+            }
+
+            Change::Updated
+        }
+        "cancel" | "auth_revoked" => Change::Cancelled,
+        _ => Change::Unchanged,
+    }
+}
+
+/// Find the value at a Firebase path, creating it along the way.
+fn pointer_mut<'a>(mut value: &'a mut Value, path: &str) -> &'a mut Value {
+    for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+        value = match (value, segment.parse::<usize>().ok()) {
+            (Value::Array(items), Some(index)) => {
+                if items.len() <= index {
+                    items.resize(index + 1, Value::Null);
+                }
+                &mut items[index]
+            }
+            (value, _) => {
+                if !value.is_object() {
+                    *value = Value::Object(Default::default());
+                }
+                value
+                    .as_object_mut()
+                    .unwrap()
+                    .entry(segment)
+                    .or_insert(Value::Null)
+            }
+        };
+    }
+
+    value
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn event(event: &str, data: Value) -> SseEvent {
+        SseEvent {
+            event: event.into(),
+            data: data.to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_events_split_across_chunks() {
+        let mut parser = SseParser::default();
+
+        let got = parser.push(b"event: put\ndata: {\"path\"");
+        assert_eq!(got, vec![]);
+
+        let got = parser.push(b":\"/\"}\r\n\r\n: comment\n\nevent: keep-alive\ndata: null\n\n");
+        let want = vec![
+            SseEvent {
+                event: "put".into(),
+                data: r#"{"path":"/"}"#.into(),
+            },
+            SseEvent {
+                event: "keep-alive".into(),
+                data: "null".into(),
+            },
+        ];
+        assert_eq!(got, want);
+    }
+
+    #[test]
+    fn decodes_characters_split_across_chunks() {
+        let mut parser = SseParser::default();
+        let body = "event: put\ndata: \"Café\"\n\n".as_bytes();
+        let split = body.iter().position(|&byte| byte == 0xc3).unwrap() + 1;
+
+        assert_eq!(parser.push(&body[..split]), vec![]);
+        let got = parser.push(&body[split..]);
+
+        assert_eq!(got, vec![event("put", json!("Café"))]);
+    }
+
+    #[test]
+    fn applies_puts_and_patches() {
+        let mut state = Value::Null;
+
+        let change = apply(
+            &mut state,
+            &event(
+                "put",
+                json!({"path": "/", "data": {"items": [1, 2], "profiles": []}}),
+            ),
+        );
+        assert_eq!(change, Change::Updated);
+
+        apply(
+            &mut state,
+            &event("put", json!({"path": "/items/1", "data": 3})),
+        );
+        assert_eq!(state, json!({"items": [1, 3], "profiles": []}));
+
+        apply(
+            &mut state,
+            &event("patch", json!({"path": "/", "data": {"profiles": ["dan"]}})),
+        );
+        assert_eq!(state, json!({"items": [1, 3], "profiles": ["dan"]}));
+    }
+
+    #[test]
+    fn ignores_keep_alives_and_reports_cancels() {
+        let mut state = json!(1);
+
+        let change = apply(&mut state, &event("keep-alive", Value::Null));
+        assert_eq!(change, Change::Unchanged);
+        assert_eq!(state, json!(1));
+
+        let change = apply(&mut state, &event("cancel", Value::Null));
+        assert_eq!(change, Change::Cancelled);
+    }
+}
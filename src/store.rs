@@ -1,41 +1,66 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use crate::{
-    db,
-    domain::{Item, Updates},
+    config::Config,
+    db::{self, Storage},
+    domain::{Item, Updates, User},
     hn_client::HnClient,
+    metrics::{self, time_query, CACHE_LOOKUPS},
     result::{Error, Result},
 };
-use futures::{stream, StreamExt};
-use sqlx::sqlite::SqlitePool;
+use chrono::Utc;
+use futures::{stream, Stream, StreamExt};
+use tracing::{debug, instrument, warn};
+
+/// The lists [`Store::get_list`] knows.
+const LISTS: &[&str] = &["top", "new", "best", "ask", "show", "job"];
 
 #[derive(Clone)]
 pub struct Store {
     client: HnClient,
-    pool: SqlitePool,
+    storage: Arc<dyn Storage>,
+    max_descendants: usize,
 }
 
 impl Store {
-    pub fn new(pool: SqlitePool) -> Self {
-        let client = HnClient::new();
+    pub fn new(storage: Arc<dyn Storage>, config: &Config) -> Self {
+        let client = HnClient::new(config.http_timeout());
 
-        Self { client, pool }
+        Self {
+            max_descendants: config.max_descendants,
+            ..Self::with_client(storage, client)
+        }
     }
 
+    pub fn with_client(storage: Arc<dyn Storage>, client: HnClient) -> Self {
+        Self {
+            client,
+            storage,
+            max_descendants: Config::default().max_descendants,
+        }
+    }
+
+    #[instrument(level = "debug", skip(self))]
     pub async fn get_item(&self, id: u32) -> Result<Option<Item>> {
-        println!("GET {}", id);
-        if let Some(item) = db::Item::load(id).fetch_optional(&self.pool).await? {
-            return Ok(Some(item.into()));
+        let item = time_query("load_item", self.storage.load_item(id)).await?;
+        if let Some(item) = item {
+            if let Some(item) = self.read(item).await? {
+                CACHE_LOOKUPS.with_label_values(&["hit"]).inc();
+                return Ok(Some(item));
+            }
         }
 
+        CACHE_LOOKUPS.with_label_values(&["miss"]).inc();
+
         self.get_and_store_item(id).await
     }
 
     pub async fn get_and_store_item(&self, id: u32) -> Result<Option<Item>> {
         if let Some(item) = self.client.get_item(id).await.ok().flatten() {
             // Store it
-            let db_item: db::Item = item.clone().into();
-            db_item.insert().execute(&self.pool).await?;
+            let db_item = db::Item::try_from(item.clone())?;
+            time_query("insert_item", self.storage.insert_items(&[db_item], None)).await?;
 
             Ok(Some(item))
         } else {
@@ -43,20 +68,66 @@ impl Store {
         }
     }
 
+    #[instrument(level = "debug", skip(self, ids), fields(count = ids.len()))]
     pub async fn get_items(&self, ids: Vec<u32>) -> Result<HashMap<u32, Item>> {
-        stream::iter(ids)
-            .map(|id| async move { Ok::<_, Error>((id, self.get_item(id).await?)) })
-            .buffer_unordered(500)
-            .fold(Ok(HashMap::new()), |output, next| async {
-                let mut output = output?;
-                if let (id, Some(story)) = next? {
-                    output.insert(id, story);
-                }
-                Ok(output)
-            })
-            .await
+        let mut items = self.get_stored_items(&ids).await?;
+
+        let misses = ids
+            .into_iter()
+            .filter(|id| !items.contains_key(id))
+            .collect::<Vec<_>>();
+
+        CACHE_LOOKUPS
+            .with_label_values(&["hit"])
+            .inc_by(items.len() as u64);
+        CACHE_LOOKUPS
+            .with_label_values(&["miss"])
+            .inc_by(misses.len() as u64);
+
+        if !misses.is_empty() {
+            debug!(misses = misses.len(), "Fetching missing items");
+            let fetched = stream::iter(misses)
+                .map(|id| async move { (id, self.client.get_item(id).await.ok().flatten()) })
+                .buffer_unordered(500)
+                .filter_map(|(id, item)| async move { item.map(|item| (id, item)) })
+                .collect::<HashMap<_, _>>()
+                .await;
+
+            self.store_items(&fetched).await?;
+            items.extend(fetched);
+        }
+
+        Ok(items)
+    }
+
+    /// The items already in storage, without going to HN for the rest.
+    pub async fn get_stored_items(&self, ids: &[u32]) -> Result<HashMap<u32, Item>> {
+        let mut items = HashMap::new();
+        for item in time_query("load_items", self.storage.load_items(ids)).await? {
+            if let Some(item) = self.read(item).await? {
+                items.insert(item.id(), item);
+            }
+        }
+
+        Ok(items)
     }
 
+    /// A stored item that can't be read back is quarantined, and treated as
+    /// missing so it's fetched from HN again.
+    async fn read(&self, item: db::Item) -> Result<Option<Item>> {
+        match Item::try_from(item.clone()) {
+            Ok(item) => Ok(Some(item)),
+            Err(err) => {
+                warn!(id = item.id(), error = %err, "Quarantining unreadable item");
+                self.storage
+                    .quarantine_item(&item, &err.to_string(), Utc::now())
+                    .await?;
+                Ok(None)
+            }
+        }
+    }
+
+    #[instrument(level = "debug", skip(self, ids), fields(count = ids.len()))]
     pub async fn get_and_store_items(&self, ids: Vec<u32>) -> Result<HashMap<u32, Item>> {
         let items = stream::iter(ids)
             .map(|id| async move { Ok::<_, Error>((id, self.client.get_item(id).await?)) })
@@ -73,24 +144,28 @@ impl Store {
             )
             .await?;
 
-        let mut tx = self.pool.begin().await?;
-        for (_, item) in items.iter() {
-            let db_item: db::Item = item.clone().into();
-            db_item.insert().execute(&mut tx).await?;
-        }
-
-        tx.commit().await?;
+        self.store_items(&items).await?;
 
         Ok(items)
     }
 
+    async fn store_items(&self, items: &HashMap<u32, Item>) -> Result<()> {
+        let items = items
+            .values()
+            .map(|item| db::Item::try_from(item.clone()))
+            .collect::<Result<Vec<_>>>()?;
+
+        metrics::time_query("store_items", self.storage.insert_items(&items, None)).await
+    }
+
+    #[instrument(level = "debug", skip(self))]
     pub async fn get_descendants(&self, id: u32) -> Result<HashMap<u32, Item>> {
         let mut results = HashMap::new();
 
         if let Some(item) = self.get_item(id).await? {
             let mut to_fetch = item.kids();
 
-            while to_fetch.len() > 0 {
+            while !to_fetch.is_empty() {
                 let children = self.get_items(to_fetch.clone()).await?;
                 to_fetch = vec![];
                 for (id, child) in children.into_iter() {
@@ -99,7 +174,7 @@ impl Store {
                 }
 
                 // fuse
-                if results.len() > 10_000 {
+                if results.len() > self.max_descendants {
                     break;
                 }
             }
@@ -108,6 +183,7 @@ impl Store {
         Ok(results)
     }
 
+    #[instrument(level = "debug", skip(self))]
     pub async fn get_ancestors(&self, id: u32) -> Result<HashMap<u32, Item>> {
         let mut results = HashMap::new();
 
@@ -122,7 +198,7 @@ impl Store {
                 }
 
                 // fuse
-                if results.len() > 10_000 {
+                if results.len() > self.max_descendants {
                     break;
                 }
             }
@@ -131,6 +207,58 @@ impl Store {
         Ok(results)
     }
 
+    /// Stories and jobs whose title contains `query`, from what's stored,
+    /// newest first.
+    pub async fn search(&self, query: &str, limit: u32, offset: u32) -> Result<Vec<Item>> {
+        let found = time_query(
+            "search_items",
+            self.storage.search_items(query, limit, offset),
+        )
+        .await?;
+
+        let mut items = Vec::with_capacity(found.len());
+        for item in found {
+            items.extend(self.read(item).await?);
+        }
+        Ok(items)
+    }
+
+    /// A user, straight from HN. Only HN's own username characters are
+    /// allowed, as the name goes into the URL.
+    pub async fn get_user(&self, name: &str) -> Result<Option<User>> {
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !valid {
+            return Err(Error::InvalidArgument(format!(
+                "invalid username {:?}",
+                name
+            )));
+        }
+
+        self.client.get_user(name).await
+    }
+
+    /// The ids on one of HN's lists, by the name used in URLs: `top`, `new`,
+    /// `best`, `ask`, `show` or `job`. Top stories come from the stored
+    /// ranking, the rest straight from HN.
+    pub async fn get_list(&self, name: &str) -> Result<Vec<u32>> {
+        match name {
+            "top" => self.storage.list("top_stories").await,
+            "new" => self.get_new_stories().await,
+            "best" => self.get_best_stories().await,
+            "ask" => self.get_ask_stories().await,
+            "show" => self.get_show_stories().await,
+            "job" => self.get_job_stories().await,
+            _ => Err(Error::NotFound(format!(
+                "no list {:?}, expected one of {}",
+                name,
+                LISTS.join(", ")
+            ))),
+        }
+    }
+
     pub async fn get_top_stories(&self) -> Result<Vec<u32>> {
         self.client.get_top_stories().await
     }
@@ -162,4 +290,16 @@ impl Store {
     pub async fn get_max_item_id(&self) -> Result<u32> {
         self.client.get_max_item_id().await
     }
+
+    pub fn watch_top_stories(&self) -> impl Stream<Item = Vec<u32>> {
+        self.client.watch_top_stories()
+    }
+
+    pub fn watch_updates(&self) -> impl Stream<Item = Updates> {
+        self.client.watch_updates()
+    }
+
+    pub fn watch_max_item_id(&self) -> impl Stream<Item = u32> {
+        self.client.watch_max_item_id()
+    }
 }